@@ -1,5 +1,4 @@
 use std::{
-    collections::HashSet,
     env,
     fmt::Formatter,
     fs::File,
@@ -23,85 +22,515 @@ impl std::fmt::Debug for Item {
     }
 }
 
-impl Item {
-    fn new(c: u8) -> Self {
-        let pri = if (b'a'..=b'z').contains(&c) {
+impl TryFrom<u8> for Item {
+    type Error = std::io::Error;
+
+    fn try_from(c: u8) -> std::io::Result<Self> {
+        let pri = if c.is_ascii_lowercase() {
             (1 + (c - b'a')).into()
-        } else if (b'A'..=b'Z').contains(&c) {
+        } else if c.is_ascii_uppercase() {
             (27 + (c - b'A')).into()
         } else {
-            panic!("unknown");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown item {:?}", char::from(c)),
+            ));
         };
 
-        return Item { val: c, pri };
+        Ok(Item { val: c, pri })
+    }
+}
+
+impl Item {
+    fn from_priority(pri: u64) -> Self {
+        let val = if pri <= 26 {
+            b'a' + (pri - 1) as u8
+        } else {
+            b'A' + (pri - 27) as u8
+        };
+        Item { val, pri }
+    }
+}
+
+/// A set of items stored as a 52-bit mask, where bit `pri - 1` marks the item with priority `pri`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct ItemSet(u64);
+
+impl ItemSet {
+    const ALL: Self = Self((1 << 52) - 1);
+
+    fn insert(&mut self, item: &Item) {
+        *self = self.union(&Self::from(item));
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    fn len(&self) -> u32 {
+        self.0.count_ones()
+    }
+
+    fn items(&self) -> impl Iterator<Item = Item> {
+        (0..self.len()).scan(self.0, |bits, _| {
+            let pri = u64::from(bits.trailing_zeros()) + 1;
+            *bits &= *bits - 1;
+            Some(Item::from_priority(pri))
+        })
+    }
+
+    fn priority(&self) -> u64 {
+        self.items().map(|item| item.pri).sum()
+    }
+}
+
+impl From<&Item> for ItemSet {
+    fn from(item: &Item) -> Self {
+        Self(1 << (item.pri - 1))
     }
 }
 
-fn part01(file: File) {
+impl FromIterator<Item> for ItemSet {
+    fn from_iter<T: IntoIterator<Item = Item>>(iter: T) -> Self {
+        let mut set = Self::default();
+        for item in iter {
+            set.insert(&item);
+        }
+        set
+    }
+}
+
+fn parse_compartments(line: &str, compartments: usize) -> std::io::Result<Vec<ItemSet>> {
+    if !line.len().is_multiple_of(compartments) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "rucksack of {} items can not be split into {} compartments",
+                line.len(),
+                compartments
+            ),
+        ));
+    }
+    let compartment_size = line.len() / compartments;
+    let bytes = line.as_bytes();
+    (0..compartments)
+        .map(|i| &bytes[i * compartment_size..(i + 1) * compartment_size])
+        .map(|compartment| compartment.iter().copied().map(Item::try_from).collect())
+        .collect()
+}
+
+fn part01(file: File, compartments: usize) -> std::io::Result<()> {
     let mut sum = 0;
 
     let reader = BufReader::new(file);
     for line in reader.lines() {
-        let line = line.unwrap();
-        let line_length = line.len();
-        let line_mid_pos = line_length / 2;
-
-        let mut sack1 = HashSet::new();
-        let mut sack2 = HashSet::new();
-        for (i, c) in line.bytes().enumerate() {
-            let item = Item::new(c);
-            if i < line_mid_pos {
-                sack1.insert(item);
-            } else {
-                sack2.insert(item);
-            }
-        }
-
-        let inter = &sack1 & &sack2;
-        for item in inter {
-            sum += item.pri;
-        }
+        let line = line?;
+        let sacks = parse_compartments(&line, compartments)?;
+        let inter = sacks
+            .iter()
+            .fold(ItemSet::ALL, |acc, sack| acc.intersection(sack));
+        sum += inter.priority();
     }
 
     println!("{}", sum);
+    Ok(())
 }
 
-fn part02(file: File) {
+fn part02(file: File, group_size: usize) -> std::io::Result<()> {
     let mut sum = 0;
-    let mut sacks = vec![HashSet::new(), HashSet::new(), HashSet::new()];
+    let mut group = ItemSet::ALL;
+    let mut group_count = 0;
 
     let reader = BufReader::new(file);
-    for (line_no, line) in reader.lines().enumerate() {
-        let line = line.unwrap();
+    for line in reader.lines() {
+        let line = line?;
+        let sack: ItemSet = line.bytes().map(Item::try_from).collect::<Result<_, _>>()?;
+        group = group.intersection(&sack);
+        group_count += 1;
+
+        if group_count == group_size {
+            sum += group.priority();
+            group = ItemSet::ALL;
+            group_count = 0;
+        }
+    }
+
+    if group_count != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "last group has {} rucksacks, expecting {}",
+                group_count, group_size
+            ),
+        ));
+    }
+
+    println!("{}", sum);
+    Ok(())
+}
 
-        for c in line.bytes() {
-            let item = Item::new(c);
-            sacks[line_no % 3].insert(item);
+#[derive(Debug)]
+enum AuditError {
+    UnknownItem {
+        line_no: usize,
+        offset: usize,
+        byte: u8,
+    },
+    UnevenCompartments {
+        line_no: usize,
+        length: usize,
+        compartments: usize,
+    },
+    IncompleteGroup {
+        first_line_no: usize,
+        count: usize,
+        group_size: usize,
+    },
+}
+
+impl std::fmt::Display for AuditError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Self::UnknownItem {
+                line_no,
+                offset,
+                byte,
+            } => write!(
+                f,
+                "line {}: unknown item {:?} at offset {}",
+                line_no,
+                char::from(*byte),
+                offset
+            ),
+            Self::UnevenCompartments {
+                line_no,
+                length,
+                compartments,
+            } => write!(
+                f,
+                "line {}: {} items can not be split into {} compartments",
+                line_no, length, compartments
+            ),
+            Self::IncompleteGroup {
+                first_line_no,
+                count,
+                group_size,
+            } => write!(
+                f,
+                "line {}: last group has {} rucksacks, expecting {}",
+                first_line_no, count, group_size
+            ),
         }
+    }
+}
 
-        if line_no % 3 == 2 {
-            let mut iter = sacks.drain(..);
-            let inter = iter
-                .next()
-                .map(|sack: HashSet<Item>| iter.fold(sack, |sack1, sack2| &sack1 & &sack2))
-                .unwrap();
-            for item in inter {
-                sum += item.pri;
+/// Checks every item of one rucksack, printing the items found in all of its compartments along
+/// with their offsets. Returns the set of valid items so that the rucksack can still take part
+/// in the badge search.
+fn audit_rucksack(
+    line_no: usize,
+    line: &str,
+    compartments: usize,
+    errors: &mut Vec<AuditError>,
+) -> ItemSet {
+    let mut sack = ItemSet::default();
+    let mut items = Vec::with_capacity(line.len());
+    for (offset, byte) in line.bytes().enumerate() {
+        match Item::try_from(byte) {
+            Ok(item) => {
+                sack.insert(&item);
+                items.push(Some(item));
+            }
+            Err(_) => {
+                errors.push(AuditError::UnknownItem {
+                    line_no,
+                    offset,
+                    byte,
+                });
+                items.push(None);
             }
-            sacks.push(HashSet::new());
-            sacks.push(HashSet::new());
-            sacks.push(HashSet::new());
         }
+    }
 
+    if !line.len().is_multiple_of(compartments) {
+        errors.push(AuditError::UnevenCompartments {
+            line_no,
+            length: line.len(),
+            compartments,
+        });
+        return sack;
     }
 
-    println!("{}", sum);
+    let compartment_size = line.len() / compartments;
+    let duplicated = items
+        .chunks(compartment_size.max(1))
+        .map(|compartment| compartment.iter().flatten().cloned().collect::<ItemSet>())
+        .fold(ItemSet::ALL, |acc, set| acc.intersection(&set));
+    if line.is_empty() || duplicated.len() == 0 {
+        println!("rucksack {}: no misplaced item", line_no);
+        return sack;
+    }
+    for item in duplicated.items() {
+        let offsets: Vec<Vec<usize>> = items
+            .chunks(compartment_size)
+            .enumerate()
+            .map(|(i, compartment)| {
+                compartment
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, other)| other.as_ref() == Some(&item))
+                    .map(|(offset, _)| i * compartment_size + offset)
+                    .collect()
+            })
+            .collect();
+        println!(
+            "rucksack {}: misplaced {:?} at offsets {:?}",
+            line_no, item, offsets
+        );
+    }
+    sack
+}
+
+/// Reports the badge of a group, and checks every rucksack for the items it shares with all the
+/// other rucksacks. Those holding none or several of them are listed along with the items all
+/// the others share but they lack, which point out the rucksack that breaks the badge.
+fn audit_group(first_line_no: usize, sacks: &[ItemSet]) {
+    let last_line_no = first_line_no + sacks.len() - 1;
+    let shared = sacks
+        .iter()
+        .fold(ItemSet::ALL, |acc, sack| acc.intersection(sack));
+    let candidates: Vec<Item> = shared.items().collect();
+    if candidates.len() == 1 {
+        println!(
+            "group {}-{}: badge {:?}",
+            first_line_no, last_line_no, candidates[0]
+        );
+    } else {
+        println!(
+            "group {}-{}: no unique badge, candidates {:?}",
+            first_line_no, last_line_no, candidates
+        );
+    }
+    for (i, sack) in sacks.iter().enumerate() {
+        let others = sacks
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .fold(ItemSet::ALL, |acc, (_, other)| acc.intersection(other));
+        let sack_candidates: Vec<Item> = sack.intersection(&others).items().collect();
+        if sack_candidates.len() != 1 {
+            let missing: Vec<Item> = others
+                .items()
+                .filter(|item| !sack_candidates.contains(item))
+                .collect();
+            println!(
+                "group {}-{}: rucksack {} shares {} items with all the others {:?}, lacks {:?}",
+                first_line_no,
+                last_line_no,
+                first_line_no + i,
+                sack_candidates.len(),
+                sack_candidates,
+                missing
+            );
+        }
+    }
+}
+
+fn audit(file: File, group_size: usize, compartments: usize) -> std::io::Result<()> {
+    let mut errors = Vec::default();
+    let mut group = Vec::with_capacity(group_size);
+    let mut line_count = 0;
+
+    let reader = BufReader::new(file);
+    for line in reader.lines() {
+        let line = line?;
+        line_count += 1;
+        let line_no = line_count;
+        group.push(audit_rucksack(line_no, &line, compartments, &mut errors));
+        if group.len() == group_size {
+            audit_group(line_no + 1 - group_size, &group);
+            group.clear();
+        }
+    }
+
+    if !group.is_empty() {
+        errors.push(AuditError::IncompleteGroup {
+            first_line_no: line_count + 1 - group.len(),
+            count: group.len(),
+            group_size,
+        });
+    }
+
+    for error in errors {
+        println!("error: {}", error);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Move {
+    item: Item,
+    offset: usize,
+    from: usize,
+    to: usize,
+}
+
+/// Chooses the compartment that keeps each item type, so that every copy left in the other
+/// compartment has to be moved. Moving a type costs its count in the compartment it leaves.
+///
+/// When `balanced` is set, both compartments must end up holding the same number of items. This
+/// is solved as a knapsack over the item types on the final size of the first compartment, and
+/// `None` is returned if no assignment can balance the rucksack.
+fn plan_sides(counts: &[[usize; 2]], balanced: bool) -> Option<Vec<usize>> {
+    if !balanced {
+        return Some(
+            counts
+                .iter()
+                .map(|[first, second]| if first >= second { 0 } else { 1 })
+                .collect(),
+        );
+    }
+
+    let total: usize = counts.iter().map(|[first, second]| first + second).sum();
+    if !total.is_multiple_of(2) {
+        return None;
+    }
+    // costs[s] is the minimal number of moves for the first compartment to hold s items, choices
+    // records the side picked for each type to reach that state.
+    let mut costs = vec![None; total + 1];
+    costs[0] = Some(0);
+    let mut choices = Vec::with_capacity(counts.len());
+    for &[first, second] in counts {
+        let mut next_costs = vec![None; total + 1];
+        let mut next_choices = vec![0; total + 1];
+        for (size, cost) in costs.iter().enumerate() {
+            let Some(cost) = cost else { continue };
+            // Keep the type in the first compartment by moving the second compartment's copies.
+            let keep_first = (size + first + second, cost + second, 0);
+            // Keep the type in the second compartment by moving the first compartment's copies.
+            let keep_second = (size, cost + first, 1);
+            for (next_size, next_cost, side) in [keep_first, keep_second] {
+                if next_costs[next_size].is_none_or(|c| next_cost < c) {
+                    next_costs[next_size] = Some(next_cost);
+                    next_choices[next_size] = side;
+                }
+            }
+        }
+        costs = next_costs;
+        choices.push(next_choices);
+    }
+
+    costs[total / 2]?;
+    let mut sides = vec![0; counts.len()];
+    let mut size = total / 2;
+    for (t, &[first, second]) in counts.iter().enumerate().rev() {
+        sides[t] = choices[t][size];
+        if sides[t] == 0 {
+            size -= first + second;
+        }
+    }
+    Some(sides)
+}
+
+/// Computes the fewest single-item moves between the two compartments after which no item type
+/// is found in both compartments.
+fn plan_repack(line: &str, balanced: bool) -> std::io::Result<Option<Vec<Move>>> {
+    let items = line
+        .bytes()
+        .map(Item::try_from)
+        .collect::<std::io::Result<Vec<_>>>()?;
+    if !items.len().is_multiple_of(2) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("rucksack of {} items can not be split in half", items.len()),
+        ));
+    }
+
+    let line_mid_pos = items.len() / 2;
+    let mut counts = vec![[0usize; 2]; 52];
+    for (offset, item) in items.iter().enumerate() {
+        counts[item.pri as usize - 1][offset / line_mid_pos.max(1)] += 1;
+    }
+    let sides = match plan_sides(&counts, balanced) {
+        Some(sides) => sides,
+        None => return Ok(None),
+    };
+
+    let moves = items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(offset, item)| {
+            let from = offset / line_mid_pos;
+            let to = sides[item.pri as usize - 1];
+            (from != to).then_some(Move {
+                item,
+                offset,
+                from: from + 1,
+                to: to + 1,
+            })
+        })
+        .collect();
+    Ok(Some(moves))
+}
+
+fn repack(file: File, balanced: bool) -> std::io::Result<()> {
+    let mut total = 0;
+
+    let reader = BufReader::new(file);
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
+        match plan_repack(&line, balanced)? {
+            None => println!("rucksack {}: can not be balanced", line_no),
+            Some(moves) if moves.is_empty() => {}
+            Some(moves) => {
+                println!("rucksack {}: {} moves", line_no, moves.len());
+                for m in &moves {
+                    println!(
+                        "  move {:?} at offset {} from compartment {} to {}",
+                        m.item, m.offset, m.from, m.to
+                    );
+                }
+                total += moves.len();
+            }
+        }
+    }
+
+    println!("{}", total);
+    Ok(())
 }
 
 fn main() {
-    let fpath = env::args()
-        .nth(1)
-        .expect("Path to input file is not given!");
-    part01(File::open(&fpath).unwrap());
-    part02(File::open(&fpath).unwrap());
+    let mut args = env::args().skip(1).peekable();
+    let fpath = args.next().expect("Path to input file is not given!");
+    let mode = args.next_if(|arg| arg == "audit" || arg == "repack");
+    let is_balanced = args.next_if_eq("balanced").is_some();
+    let group_size = args
+        .next()
+        .map(|s| s.parse().expect("Group size must be a number!"))
+        .unwrap_or(3);
+    let compartments = args
+        .next()
+        .map(|s| s.parse().expect("Compartment count must be a number!"))
+        .unwrap_or(2);
+    assert!(group_size >= 2, "Group size must be at least 2!");
+    assert!(compartments >= 2, "Compartment count must be at least 2!");
+
+    match mode.as_deref() {
+        Some("audit") => {
+            audit(File::open(&fpath).unwrap(), group_size, compartments).unwrap();
+            return;
+        }
+        Some("repack") => {
+            repack(File::open(&fpath).unwrap(), is_balanced).unwrap();
+            return;
+        }
+        _ => {}
+    }
+    part01(File::open(&fpath).unwrap(), compartments).unwrap();
+    part02(File::open(&fpath).unwrap(), group_size).unwrap();
 }
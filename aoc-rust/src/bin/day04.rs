@@ -56,10 +56,328 @@ fn part02(file: File) {
     println!("{}", count);
 }
 
+/// Sections split into maximal segments that are covered by the same number of elves. Segment
+/// `i` spans the sections `boundaries[i]..boundaries[i + 1]`.
+struct Coverage {
+    boundaries: Vec<u64>,
+    counts: Vec<u64>,
+    // sparse_min[k][i] is the minimum count of the segments i..i + 2^k.
+    sparse_min: Vec<Vec<u64>>,
+}
+
+impl Coverage {
+    fn new(sections: &[RangeInclusive<u64>]) -> Self {
+        let mut events = Vec::with_capacity(sections.len() * 2);
+        for section in sections {
+            events.push((*section.start(), 1i64));
+            events.push((section.end() + 1, -1i64));
+        }
+        events.sort_unstable();
+
+        let mut boundaries = Vec::default();
+        let mut counts = Vec::default();
+        let mut count = 0i64;
+        for (pos, delta) in events {
+            if boundaries.last() != Some(&pos) {
+                if !boundaries.is_empty() {
+                    counts.push(count as u64);
+                }
+                boundaries.push(pos);
+            }
+            count += delta;
+        }
+
+        let mut sparse_min = vec![counts.clone()];
+        let mut width = 1;
+        while width * 2 <= counts.len() {
+            let prev = sparse_min.last().unwrap();
+            let next = (0..=counts.len() - width * 2)
+                .map(|i| prev[i].min(prev[i + width]))
+                .collect();
+            sparse_min.push(next);
+            width *= 2;
+        }
+
+        Self {
+            boundaries,
+            counts,
+            sparse_min,
+        }
+    }
+
+    fn segments(&self) -> impl Iterator<Item = (RangeInclusive<u64>, u64)> + '_ {
+        self.boundaries
+            .windows(2)
+            .zip(self.counts.iter())
+            .map(|(bounds, count)| (bounds[0]..=bounds[1] - 1, *count))
+    }
+
+    /// Returns the fewest elves covering any section in the range. The range must start and end
+    /// on segment boundaries, which holds for any range built from the assigned sections.
+    fn min_count(&self, section: &RangeInclusive<u64>) -> u64 {
+        let first = self.boundaries.binary_search(section.start()).unwrap();
+        let last = self.boundaries.binary_search(&(section.end() + 1)).unwrap();
+        let level = (usize::BITS - (last - first).leading_zeros() - 1) as usize;
+        let width = 1 << level;
+        self.sparse_min[level][first].min(self.sparse_min[level][last - width])
+    }
+}
+
+/// Checks whether every section of `section` stays covered by another elf when the pair made of
+/// `section` and `partner` is removed from the team.
+fn is_redundant(
+    coverage: &Coverage,
+    section: &RangeInclusive<u64>,
+    partner: &RangeInclusive<u64>,
+) -> bool {
+    let shared_start = *section.start().max(partner.start());
+    let shared_end = *section.end().min(partner.end());
+    if shared_start > shared_end {
+        return coverage.min_count(section) >= 2;
+    }
+    let mut is_covered = coverage.min_count(&(shared_start..=shared_end)) >= 3;
+    if section.start() < &shared_start {
+        is_covered &= coverage.min_count(&(*section.start()..=shared_start - 1)) >= 2;
+    }
+    if section.end() > &shared_end {
+        is_covered &= coverage.min_count(&(shared_end + 1..=*section.end())) >= 2;
+    }
+    is_covered
+}
+
+fn coverage(file: File) {
+    let reader = BufReader::new(file);
+    let pairs: Vec<ElfPair> = reader
+        .lines()
+        .map(|line| parse_elf_pair(&line.unwrap()))
+        .collect();
+    let sections: Vec<RangeInclusive<u64>> = pairs
+        .iter()
+        .flat_map(|pair| [pair.0.clone(), pair.1.clone()])
+        .collect();
+    let coverage = Coverage::new(&sections);
+
+    let mut covered_by_one = 0;
+    let mut covered_by_several = 0;
+    let mut covered_by_none = 0;
+    let mut most_overloaded = None;
+    for (section, count) in coverage.segments() {
+        let length = section.end() - section.start() + 1;
+        match count {
+            0 => covered_by_none += length,
+            1 => covered_by_one += length,
+            _ => covered_by_several += length,
+        }
+        if most_overloaded.as_ref().is_none_or(|(_, max)| count > *max) {
+            most_overloaded = Some((*section.start(), count));
+        }
+    }
+    println!("sections covered: {}", covered_by_one + covered_by_several);
+    println!("sections covered by one elf: {}", covered_by_one);
+    println!("sections covered by several elves: {}", covered_by_several);
+    println!("sections covered by no elf: {}", covered_by_none);
+    if let Some((section, count)) = most_overloaded {
+        println!("most overloaded section: {} ({} elves)", section, count);
+    }
+
+    println!("redundant pairs:");
+    for (idx, pair) in pairs.iter().enumerate() {
+        if is_redundant(&coverage, &pair.0, &pair.1) && is_redundant(&coverage, &pair.1, &pair.0) {
+            println!(
+                "  line {}: {}-{},{}-{}",
+                idx + 1,
+                pair.0.start(),
+                pair.0.end(),
+                pair.1.start(),
+                pair.1.end()
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Assignment {
+    line_no: usize,
+    elf: usize,
+    section: RangeInclusive<u64>,
+}
+
+/// A static interval tree over all assignments. The assignments are sorted by their start and
+/// the tree is implicit: the node of the index range `lo..hi` is `(lo + hi) / 2`, and `max_ends`
+/// holds the largest end found in the subtree of each node.
+struct SectionIndex {
+    assignments: Vec<Assignment>,
+    max_ends: Vec<u64>,
+}
+
+impl SectionIndex {
+    fn new(pairs: &[ElfPair]) -> Self {
+        let mut assignments: Vec<Assignment> = pairs
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, pair)| {
+                [(1, &pair.0), (2, &pair.1)].map(|(elf, section)| Assignment {
+                    line_no: idx + 1,
+                    elf,
+                    section: section.clone(),
+                })
+            })
+            .collect();
+        assignments.sort_by_key(|a| (*a.section.start(), *a.section.end()));
+
+        let mut index = Self {
+            max_ends: vec![0; assignments.len()],
+            assignments,
+        };
+        index.build(0, index.assignments.len());
+        index
+    }
+
+    fn build(&mut self, lo: usize, hi: usize) -> u64 {
+        if lo >= hi {
+            return 0;
+        }
+        let mid = (lo + hi) / 2;
+        let max_end = self
+            .build(lo, mid)
+            .max(self.build(mid + 1, hi))
+            .max(*self.assignments[mid].section.end());
+        self.max_ends[mid] = max_end;
+        max_end
+    }
+
+    fn visit_overlaps<'a>(
+        &'a self,
+        lo: usize,
+        hi: usize,
+        section: &RangeInclusive<u64>,
+        found: &mut Vec<&'a Assignment>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        if self.max_ends[mid] < *section.start() {
+            return;
+        }
+        self.visit_overlaps(lo, mid, section, found);
+        let assignment = &self.assignments[mid];
+        if assignment.section.start() > section.end() {
+            return;
+        }
+        if is_overlap(&assignment.section, section) || is_overlap(section, &assignment.section) {
+            found.push(assignment);
+        }
+        self.visit_overlaps(mid + 1, hi, section, found);
+    }
+
+    /// Returns the assignments sharing at least one section with the given range.
+    fn overlapping(&self, section: &RangeInclusive<u64>) -> Vec<&Assignment> {
+        let mut found = Vec::default();
+        self.visit_overlaps(0, self.assignments.len(), section, &mut found);
+        found
+    }
+
+    /// Returns the assignments covering the given section.
+    fn covering(&self, section: u64) -> Vec<&Assignment> {
+        self.overlapping(&(section..=section))
+    }
+
+    /// Returns the assignments that fully contain the given range.
+    fn containing(&self, section: &RangeInclusive<u64>) -> Vec<&Assignment> {
+        let mut found = self.covering(*section.start());
+        found.retain(|a| is_fully_contains(&a.section, section));
+        found
+    }
+
+    /// Returns the assignments that lie fully inside the given range.
+    fn contained_in(&self, section: &RangeInclusive<u64>) -> Vec<&Assignment> {
+        let first = self
+            .assignments
+            .partition_point(|a| a.section.start() < section.start());
+        self.assignments[first..]
+            .iter()
+            .take_while(|a| a.section.start() <= section.end())
+            .filter(|a| is_fully_contains(section, &a.section))
+            .collect()
+    }
+}
+
+fn parse_query_section(token: Option<&str>) -> std::io::Result<RangeInclusive<u64>> {
+    let token = token.ok_or(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "expecting a section range",
+    ))?;
+    let (start, end) = token.split_once('-').unwrap_or((token, token));
+    let start = start
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let end = end
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    Ok(start..=end)
+}
+
+fn run_query<'a>(index: &'a SectionIndex, query: &str) -> std::io::Result<Vec<&'a Assignment>> {
+    let mut tokens = query.split_whitespace();
+    match tokens.next() {
+        Some("point") => Ok(index.covering(*parse_query_section(tokens.next())?.start())),
+        Some("overlap") => Ok(index.overlapping(&parse_query_section(tokens.next())?)),
+        Some("contains") => Ok(index.containing(&parse_query_section(tokens.next())?)),
+        Some("within") => Ok(index.contained_in(&parse_query_section(tokens.next())?)),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "expecting one of 'point', 'overlap', 'contains' or 'within'",
+        )),
+    }
+}
+
+fn query(file: File) {
+    let reader = BufReader::new(file);
+    let pairs: Vec<ElfPair> = reader
+        .lines()
+        .map(|line| parse_elf_pair(&line.unwrap()))
+        .collect();
+    let index = SectionIndex::new(&pairs);
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line.unwrap();
+        if line.trim().is_empty() {
+            continue;
+        }
+        match run_query(&index, &line) {
+            Ok(found) => {
+                println!("{}: {} assignments", line.trim(), found.len());
+                for a in found {
+                    println!(
+                        "  line {} elf {}: {}-{}",
+                        a.line_no,
+                        a.elf,
+                        a.section.start(),
+                        a.section.end()
+                    );
+                }
+            }
+            Err(e) => println!("{}: {}", line.trim(), e),
+        }
+    }
+}
+
 fn main() {
     let fpath = env::args()
         .nth(1)
         .expect("Path to input file is not given!");
+    match env::args().nth(2).as_deref() {
+        Some("coverage") => {
+            coverage(File::open(&fpath).unwrap());
+            return;
+        }
+        Some("query") => {
+            query(File::open(&fpath).unwrap());
+            return;
+        }
+        _ => {}
+    }
     part01(File::open(&fpath).unwrap());
     part02(File::open(&fpath).unwrap());
 }
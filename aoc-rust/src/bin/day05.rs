@@ -3,6 +3,8 @@ use std::{
     env,
     fs::File,
     io::{BufRead, BufReader, Lines},
+    iter::Enumerate,
+    ops::Range,
 };
 
 #[derive(Debug)]
@@ -11,12 +13,82 @@ enum Token {
     StackId(u32),
 }
 
+const TAB_WIDTH: usize = 4;
+
 impl Token {
-    fn parse(s: &str) -> Option<Self> {
-        if let (Some(begin), Some(end)) = (s.find('['), s.find(']')) {
-            return Some(Token::Crate(String::from(&s[begin + 1..end])));
+    /// Splits a line of the drawing into tokens along with the character columns they span. Tabs
+    /// are expanded to the next multiple of `TAB_WIDTH` columns.
+    fn tokenize(line_no: usize, line: &str) -> std::io::Result<Vec<(Range<usize>, Self)>> {
+        let mut chars = Vec::with_capacity(line.len());
+        for c in line.chars() {
+            if c == '\t' {
+                let spaces = TAB_WIDTH - chars.len() % TAB_WIDTH;
+                chars.extend(std::iter::repeat_n(' ', spaces));
+            } else {
+                chars.push(c);
+            }
+        }
+
+        let mut tokens = Vec::default();
+        let mut col = 0;
+        while col < chars.len() {
+            let start = col;
+            match chars[col] {
+                c if c.is_whitespace() => {
+                    col += 1;
+                }
+                '[' => {
+                    let end = chars[start + 1..]
+                        .iter()
+                        .position(|c| *c == ']' || *c == '[')
+                        .map(|offset| start + 1 + offset)
+                        .filter(|end| chars[*end] == ']')
+                        .ok_or(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!(
+                                "line {}: crate at column {} is not closed",
+                                line_no,
+                                start + 1
+                            ),
+                        ))?;
+                    let label: String = chars[start + 1..end].iter().collect();
+                    if label.trim().is_empty() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!(
+                                "line {}: crate at column {} has no label",
+                                line_no,
+                                start + 1
+                            ),
+                        ));
+                    }
+                    col = end + 1;
+                    tokens.push((start..col, Token::Crate(label)));
+                }
+                c if c.is_ascii_digit() => {
+                    while col < chars.len() && chars[col].is_ascii_digit() {
+                        col += 1;
+                    }
+                    let id: String = chars[start..col].iter().collect();
+                    let id = id
+                        .parse()
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                    tokens.push((start..col, Token::StackId(id)));
+                }
+                c => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "line {}: unexpected character {:?} at column {}",
+                            line_no,
+                            c,
+                            start + 1
+                        ),
+                    ))
+                }
+            }
         }
-        s.trim().parse().ok().map(Self::StackId)
+        Ok(tokens)
     }
 }
 
@@ -27,6 +99,12 @@ struct Inst {
     to: u32,
 }
 
+impl std::fmt::Display for Inst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "move {} from {} to {}", self.count, self.from, self.to)
+    }
+}
+
 impl Inst {
     fn parse(s: &str) -> Option<Self> {
         let mut tokens = s.split_whitespace();
@@ -44,102 +122,814 @@ impl Inst {
     }
 }
 
-fn parse_state<R: BufRead>(lines: &mut Lines<R>) -> BTreeMap<u32, VecDeque<String>> {
-    let mut stacks = Vec::default();
-    let mut ids = Vec::default();
-    for line in lines {
-        let line = line.unwrap();
-        if line.len() == 0 {
+type Stacks = BTreeMap<u32, VecDeque<String>>;
+
+#[derive(Debug)]
+struct Step {
+    line_no: usize,
+    inst: Inst,
+}
+
+#[derive(Debug)]
+enum InvalidStep {
+    Unparsable {
+        line_no: usize,
+        line: String,
+    },
+    UnknownStack {
+        line_no: usize,
+        id: u32,
+    },
+    NotEnoughCrates {
+        line_no: usize,
+        id: u32,
+        count: u64,
+        available: usize,
+    },
+    StackTooHigh {
+        line_no: usize,
+        id: u32,
+        height: usize,
+        max_height: usize,
+    },
+}
+
+impl std::fmt::Display for InvalidStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unparsable { line_no, line } => {
+                write!(f, "line {}: can not parse instruction {:?}", line_no, line)
+            }
+            Self::UnknownStack { line_no, id } => {
+                write!(f, "line {}: stack {} does not exist", line_no, id)
+            }
+            Self::NotEnoughCrates {
+                line_no,
+                id,
+                count,
+                available,
+            } => write!(
+                f,
+                "line {}: moving {} crates from stack {} which holds {}",
+                line_no, count, id, available
+            ),
+            Self::StackTooHigh {
+                line_no,
+                id,
+                height,
+                max_height,
+            } => write!(
+                f,
+                "line {}: stack {} reaches {} crates, more than the limit of {}",
+                line_no, id, height, max_height
+            ),
+        }
+    }
+}
+
+/// Reads the drawing of the stacks. Crates are matched to the stack whose id shares a column
+/// with them on the last line of the drawing, so that columns of any width can be read.
+fn parse_state<R: BufRead>(lines: &mut Enumerate<Lines<R>>) -> std::io::Result<Stacks> {
+    let mut rows = Vec::default();
+    for (idx, line) in lines {
+        let line = line?;
+        if line.trim().is_empty() {
             break;
         }
-        for (idx, chunk) in line.as_bytes().chunks(4).enumerate() {
-            let chunk = std::str::from_utf8(&chunk[0..3]).unwrap();
-            if idx >= stacks.len() {
-                stacks.push(VecDeque::default());
+        rows.push(Token::tokenize(idx + 1, &line)?);
+    }
+    let id_line_no = rows.len();
+    let id_row = rows.pop().ok_or(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "expecting a drawing of the stacks",
+    ))?;
+
+    let mut columns = Vec::with_capacity(id_row.len());
+    let mut stacks = BTreeMap::new();
+    for (span, token) in id_row {
+        match token {
+            Token::StackId(id) => {
+                if stacks.insert(id, VecDeque::default()).is_some() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("line {}: stack {} is drawn twice", id_line_no, id),
+                    ));
+                }
+                columns.push((span, id));
+            }
+            Token::Crate(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("line {}: expecting stack ids below the crates", id_line_no),
+                ))
             }
-            match Token::parse(chunk) {
-                Some(Token::Crate(c)) => {
-                    stacks[idx].push_front(c);
+        }
+    }
+
+    for (row_idx, row) in rows.into_iter().enumerate().rev() {
+        let line_no = row_idx + 1;
+        let height = id_line_no - 1 - line_no;
+        for (span, token) in row {
+            let label = match token {
+                Token::Crate(label) => label,
+                Token::StackId(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "line {}: stack id at column {} is above the crates",
+                            line_no,
+                            span.start + 1
+                        ),
+                    ))
                 }
-                Some(Token::StackId(id)) => {
-                    ids.push(id);
+            };
+            let mut ids = columns
+                .iter()
+                .filter(|(column, _)| column.start < span.end && span.start < column.end)
+                .map(|(_, id)| *id);
+            let id = match (ids.next(), ids.next()) {
+                (Some(id), None) => id,
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "line {}: crate [{}] is not above exactly one stack id",
+                            line_no, label
+                        ),
+                    ))
                 }
-                None => {}
+            };
+            let stack = stacks.get_mut(&id).unwrap();
+            if stack.len() != height {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "line {}: crate [{}] is not resting on top of stack {}",
+                        line_no, label, id
+                    ),
+                ));
             }
+            stack.push_back(label);
         }
     }
-    let mut stacks_map = BTreeMap::new();
-    for (idx, stack) in stacks.drain(..).enumerate() {
-        stacks_map.insert(ids[idx], stack);
-    }
-    stacks_map
+    Ok(stacks)
 }
 
-fn parse_steps<R: BufRead>(lines: &mut Lines<R>) -> Vec<Inst> {
+fn parse_steps<R: BufRead>(lines: &mut Enumerate<Lines<R>>) -> Vec<Result<Step, InvalidStep>> {
     let mut steps = Vec::default();
-    for line in lines {
+    for (idx, line) in lines {
         let line = line.unwrap();
-        if line.len() == 0 {
+        if line.is_empty() {
             break;
         }
-        if let Some(inst) = Inst::parse(&line) {
-            steps.push(inst);
-        }
+        let line_no = idx + 1;
+        steps.push(match Inst::parse(&line) {
+            Some(inst) => Ok(Step { line_no, inst }),
+            None => Err(InvalidStep::Unparsable { line_no, line }),
+        });
     }
     steps
 }
 
-fn part01(file: File) {
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines();
-    let mut state = parse_state(&mut lines);
-    let steps = parse_steps(&mut lines);
-    for step in steps {
-        let mut items = Vec::default();
-        let stack1 = state.get_mut(&step.from).unwrap();
-        for _ in 0..step.count {
-            if let Some(item) = stack1.pop_back() {
-                items.push(item)
+fn check_step(state: &Stacks, step: &Step) -> Result<(), InvalidStep> {
+    let line_no = step.line_no;
+    if !state.contains_key(&step.inst.to) {
+        return Err(InvalidStep::UnknownStack {
+            line_no,
+            id: step.inst.to,
+        });
+    }
+    let available = state
+        .get(&step.inst.from)
+        .ok_or(InvalidStep::UnknownStack {
+            line_no,
+            id: step.inst.from,
+        })?
+        .len();
+    if step.inst.count > available as u64 {
+        return Err(InvalidStep::NotEnoughCrates {
+            line_no,
+            id: step.inst.from,
+            count: step.inst.count,
+            available,
+        });
+    }
+    Ok(())
+}
+
+/// A crane model moving the crates of an instruction through a number of lifts. Each lift picks
+/// up at most `max_lift` crates from the top of a stack and puts them down keeping their order.
+trait Crane {
+    /// Largest number of crates picked up by a single lift.
+    fn max_lift(&self) -> usize;
+
+    /// Largest number of crates a stack can hold, if it is limited.
+    fn max_height(&self) -> Option<usize> {
+        None
+    }
+
+    /// Time taken by a lift of `count` crates.
+    fn lift_cost(&self, _count: usize) -> u64 {
+        1
+    }
+}
+
+struct CrateMover9000;
+
+impl Crane for CrateMover9000 {
+    fn max_lift(&self) -> usize {
+        1
+    }
+}
+
+struct CrateMover9001;
+
+impl Crane for CrateMover9001 {
+    fn max_lift(&self) -> usize {
+        usize::MAX
+    }
+}
+
+#[derive(Debug)]
+struct CustomCrane {
+    max_lift: usize,
+    max_height: Option<usize>,
+    cost_per_lift: u64,
+    cost_per_crate: u64,
+}
+
+impl Default for CustomCrane {
+    fn default() -> Self {
+        Self {
+            max_lift: usize::MAX,
+            max_height: None,
+            cost_per_lift: 1,
+            cost_per_crate: 0,
+        }
+    }
+}
+
+impl CustomCrane {
+    fn parse<'a, I>(options: I) -> std::io::Result<Self>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut crane = Self::default();
+        for option in options {
+            let (key, value) = option.split_once('=').ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("expecting 'key=value' but got {:?}", option),
+            ))?;
+            let value: u64 = value
+                .parse()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            match key {
+                "lift" => crane.max_lift = value.max(1) as usize,
+                "height" => crane.max_height = Some(value as usize),
+                "cost" => crane.cost_per_lift = value,
+                "crate-cost" => crane.cost_per_crate = value,
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("unknown crane option {:?}", key),
+                    ))
+                }
+            }
+        }
+        Ok(crane)
+    }
+}
+
+impl Crane for CustomCrane {
+    fn max_lift(&self) -> usize {
+        self.max_lift
+    }
+
+    fn max_height(&self) -> Option<usize> {
+        self.max_height
+    }
+
+    fn lift_cost(&self, count: usize) -> u64 {
+        self.cost_per_lift + self.cost_per_crate * count as u64
+    }
+}
+
+#[derive(Debug, Default)]
+struct CraneSummary {
+    lifts: u64,
+    cost: u64,
+}
+
+/// Moves the crates of one step and returns the number of lifts along with their cost. Steps
+/// referring to unknown stacks are skipped, and a stack holding fewer crates than requested gives
+/// up all of its crates.
+fn operate(
+    crane: &dyn Crane,
+    state: &mut Stacks,
+    step: &Step,
+) -> Result<CraneSummary, InvalidStep> {
+    let mut summary = CraneSummary::default();
+    let inst = &step.inst;
+    if !state.contains_key(&inst.to) || !state.contains_key(&inst.from) {
+        return Ok(summary);
+    }
+    let mut remaining = inst.count as usize;
+    while remaining > 0 {
+        let stack = state.get_mut(&inst.from).unwrap();
+        let count = remaining.min(crane.max_lift()).min(stack.len());
+        if count == 0 {
+            break;
+        }
+        let items = stack.split_off(stack.len() - count);
+        let stack = state.get_mut(&inst.to).unwrap();
+        stack.extend(items);
+        if let Some(max_height) = crane.max_height() {
+            if stack.len() > max_height {
+                return Err(InvalidStep::StackTooHigh {
+                    line_no: step.line_no,
+                    id: inst.to,
+                    height: stack.len(),
+                    max_height,
+                });
+            }
+        }
+        remaining -= count;
+        summary.lifts += 1;
+        summary.cost += crane.lift_cost(count);
+    }
+    Ok(summary)
+}
+
+/// A step applied to the stacks, holding enough to undo it.
+#[derive(Debug)]
+struct Applied {
+    from: u32,
+    to: u32,
+    // The crates taken from the top of the source stack, bottom first.
+    crates: Vec<String>,
+    summary: CraneSummary,
+}
+
+impl Applied {
+    fn undo(self, state: &mut Stacks) {
+        if self.from == self.to {
+            return;
+        }
+        if let Some(stack) = state.get_mut(&self.to) {
+            stack.truncate(stack.len() - self.crates.len());
+        }
+        if let Some(stack) = state.get_mut(&self.from) {
+            stack.extend(self.crates);
+        }
+    }
+}
+
+/// Moves the crates of one step through `operate`. A step that fails leaves the stacks untouched.
+fn apply(crane: &dyn Crane, state: &mut Stacks, step: &Step) -> Result<Applied, InvalidStep> {
+    let source = state.get(&step.inst.from);
+    let source_len = source.map_or(0, VecDeque::len);
+    let taken = source_len.saturating_sub(step.inst.count as usize);
+    let top = source.map_or(Vec::default(), |stack| {
+        stack.range(taken..).cloned().collect::<Vec<_>>()
+    });
+
+    let result = operate(crane, state, step);
+    let moved = source_len - state.get(&step.inst.from).map_or(0, VecDeque::len);
+    let applied = Applied {
+        from: step.inst.from,
+        to: step.inst.to,
+        crates: top[top.len() - moved..].to_vec(),
+        summary: CraneSummary::default(),
+    };
+    match result {
+        Ok(summary) => Ok(Applied { summary, ..applied }),
+        Err(e) => {
+            applied.undo(state);
+            Err(e)
+        }
+    }
+}
+
+/// Runs a procedure step by step while keeping the history of the applied steps, so that the
+/// stacks can be moved to any position between the initial state and the end of the procedure.
+struct Simulation<'a> {
+    crane: &'a dyn Crane,
+    state: Stacks,
+    steps: Vec<&'a Step>,
+    history: Vec<Applied>,
+}
+
+impl<'a> Simulation<'a> {
+    fn new(crane: &'a dyn Crane, state: Stacks, steps: &'a [Result<Step, InvalidStep>]) -> Self {
+        Self {
+            crane,
+            state,
+            steps: steps.iter().flatten().collect(),
+            history: Vec::default(),
+        }
+    }
+
+    /// Number of steps applied so far.
+    fn position(&self) -> usize {
+        self.history.len()
+    }
+
+    fn forward(&mut self) -> Result<bool, InvalidStep> {
+        match self.steps.get(self.history.len()) {
+            Some(step) => {
+                let applied = apply(self.crane, &mut self.state, step)?;
+                self.history.push(applied);
+                Ok(true)
             }
+            None => Ok(false),
         }
-        drop(stack1);
-        let stack2 = state.get_mut(&step.to).unwrap();
-        for item in items {
-            stack2.push_back(item);
+    }
+
+    fn backward(&mut self) -> bool {
+        match self.history.pop() {
+            Some(applied) => {
+                applied.undo(&mut self.state);
+                true
+            }
+            None => false,
         }
     }
 
-    for (_, v) in state {
-        print!("{}", v.back().unwrap());
+    fn seek(&mut self, position: usize) -> Result<(), InvalidStep> {
+        while self.position() > position && self.backward() {}
+        while self.position() < position && self.forward()? {}
+        Ok(())
+    }
+
+    /// Finds the first position at which `predicate` holds, scanning forward from the start, and
+    /// stops there. Returns `None` if it never holds.
+    fn find_first<P>(&mut self, predicate: P) -> Result<Option<usize>, InvalidStep>
+    where
+        P: Fn(&Stacks) -> bool,
+    {
+        self.seek(0)?;
+        loop {
+            if predicate(&self.state) {
+                return Ok(Some(self.position()));
+            }
+            if !self.forward()? {
+                return Ok(None);
+            }
+        }
     }
-    println!();
 }
 
-fn part02(file: File) {
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines();
-    let mut state = parse_state(&mut lines);
-    let steps = parse_steps(&mut lines);
+/// Describes how each stack changed from `before` to `after`, listing the crates removed from
+/// and added to the top of the part they have in common.
+fn diff(before: &Stacks, after: &Stacks) -> Vec<String> {
+    let mut changes = Vec::default();
+    for (id, old) in before {
+        let empty = VecDeque::default();
+        let new = after.get(id).unwrap_or(&empty);
+        let common = old
+            .iter()
+            .zip(new.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if common == old.len() && common == new.len() {
+            continue;
+        }
+        let removed = old.range(common..).map(|c| format!(" -[{}]", c));
+        let added = new.range(common..).map(|c| format!(" +[{}]", c));
+        changes.push(format!(
+            "{}:{}",
+            id,
+            removed.chain(added).collect::<String>()
+        ));
+    }
+    changes
+}
+
+/// Draws the stacks in the same format as the puzzle input. Every column is as wide as the
+/// widest crate or stack id, crates are left-aligned within their column and ids are centered.
+fn render(state: &Stacks) -> String {
+    let label_width = state.values().flatten().map(|c| c.len() + 2).max();
+    let id_width = state.keys().map(|id| id.to_string().len()).max();
+    let width = label_width.unwrap_or(3).max(id_width.unwrap_or(1));
+    let height = state.values().map(VecDeque::len).max().unwrap_or(0);
+
+    let mut lines = Vec::with_capacity(height + 1);
+    for row in (0..height).rev() {
+        let cells: Vec<String> = state
+            .values()
+            .map(|stack| match stack.get(row) {
+                Some(label) => format!("{:<width$}", format!("[{}]", label)),
+                None => " ".repeat(width),
+            })
+            .collect();
+        lines.push(cells.join(" "));
+    }
+    let ids: Vec<String> = state
+        .keys()
+        .map(|id| {
+            let id = id.to_string();
+            let pad_left = (width - id.len()) / 2;
+            format!("{}{:<w$}", " ".repeat(pad_left), id, w = width - pad_left)
+        })
+        .collect();
+    lines.push(ids.join(" "));
+
+    let mut drawing = lines.join("\n");
+    drawing.push('\n');
+    drawing
+}
+
+/// Runs the procedure and reports every invalid instruction along with the stacks at the moment
+/// it is reached. Returns the number of invalid instructions.
+fn validate(state: &mut Stacks, steps: &[Result<Step, InvalidStep>]) -> usize {
+    let mut invalid_count = 0;
     for step in steps {
-        let stack1 = state.get_mut(&step.from).unwrap();
-        let items = stack1.split_off(stack1.len() - step.count as usize);
-        drop(stack1);
-        let stack2 = state.get_mut(&step.to).unwrap();
-        for item in items {
-            stack2.push_back(item);
+        let error = match step {
+            Ok(step) => match check_step(state, step) {
+                Ok(()) => {
+                    apply(&CrateMover9000, state, step).unwrap();
+                    continue;
+                }
+                Err(e) => e,
+            },
+            Err(e) => {
+                println!("{}", e);
+                invalid_count += 1;
+                continue;
+            }
+        };
+        println!("{}", error);
+        print!("{}", render(state));
+        invalid_count += 1;
+        if let Ok(step) = step {
+            apply(&CrateMover9000, state, step).unwrap();
         }
     }
+    invalid_count
+}
 
-    for (_, v) in state {
-        print!("{}", v.back().unwrap());
+fn run(
+    crane: &dyn Crane,
+    state: &mut Stacks,
+    steps: &[Result<Step, InvalidStep>],
+) -> Result<CraneSummary, InvalidStep> {
+    let mut summary = CraneSummary::default();
+    for step in steps.iter().flatten() {
+        let applied = apply(crane, state, step)?;
+        summary.lifts += applied.summary.lifts;
+        summary.cost += applied.summary.cost;
     }
-    println!();
+    Ok(summary)
+}
+
+/// Prints the drawing of the stacks before the first step and after each of the following steps.
+fn trace(
+    crane: &dyn Crane,
+    state: &mut Stacks,
+    steps: &[Result<Step, InvalidStep>],
+) -> Result<(), InvalidStep> {
+    print!("{}", render(state));
+    for step in steps.iter().flatten() {
+        apply(crane, state, step)?;
+        println!();
+        println!("{}", step.inst);
+        println!();
+        print!("{}", render(state));
+    }
+    Ok(())
+}
+
+fn parse_position(arg: Option<&String>) -> usize {
+    arg.expect("Step position is not given!")
+        .parse()
+        .expect("Step position must be a number!")
+}
+
+/// Runs the `rewind`, `bisect` and `diff` subcommands over a recorded simulation.
+fn history(
+    mode: &str,
+    crane: &dyn Crane,
+    state: Stacks,
+    steps: &[Result<Step, InvalidStep>],
+    args: &[String],
+) -> Result<(), InvalidStep> {
+    match mode {
+        "rewind" => {
+            let mut simulation = Simulation::new(crane, state, steps);
+            simulation.seek(steps.len())?;
+            simulation.seek(parse_position(args.first()))?;
+            println!("after {} steps", simulation.position());
+            print!("{}", render(&simulation.state));
+        }
+        "bisect" => {
+            // Crates are told apart by their starting position, given as `<stack>:<height>`,
+            // so the simulation runs over stacks labelled with those positions.
+            let (id, height) = args
+                .first()
+                .and_then(|arg| arg.split_once(':'))
+                .expect("Crate is not given as <stack>:<height>!");
+            let label = format!("{}:{}", id, height);
+            let target: u32 = parse_position(args.get(1)) as u32;
+            let tagged = state
+                .iter()
+                .map(|(id, stack)| {
+                    let labels = (1..=stack.len()).map(|h| format!("{}:{}", id, h));
+                    (*id, labels.collect())
+                })
+                .collect();
+            let mut simulation = Simulation::new(crane, tagged, steps);
+            let found = simulation.find_first(|state| {
+                state
+                    .get(&target)
+                    .is_some_and(|stack| stack.contains(&label))
+            })?;
+            match found {
+                Some(0) => println!("crate {} starts on stack {}", label, target),
+                Some(position) => {
+                    let step = simulation.steps[position - 1];
+                    println!(
+                        "crate {} reaches stack {} at step {} (line {}: {})",
+                        label, target, position, step.line_no, step.inst
+                    );
+                }
+                None => println!("crate {} never reaches stack {}", label, target),
+            }
+        }
+        "diff" => {
+            let mut simulation = Simulation::new(crane, state, steps);
+            simulation.seek(parse_position(args.first()))?;
+            let before = simulation.state.clone();
+            simulation.seek(parse_position(args.get(1)))?;
+            for change in diff(&before, &simulation.state) {
+                println!("{}", change);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn top_crates(state: &Stacks) -> String {
+    state.values().filter_map(|v| v.back().cloned()).collect()
 }
 
 fn main() {
-    let fpath = env::args()
-        .nth(1)
-        .expect("Path to input file is not given!");
-    part01(File::open(&fpath).unwrap());
-    part02(File::open(&fpath).unwrap());
+    let mut args = env::args().skip(1);
+    let fpath = args.next().expect("Path to input file is not given!");
+    let mode = args.next();
+
+    let reader = BufReader::new(File::open(&fpath).unwrap());
+    let mut lines = reader.lines().enumerate();
+    let state = parse_state(&mut lines).unwrap();
+    let steps = parse_steps(&mut lines);
+
+    match mode.as_deref() {
+        Some("validate") => {
+            let invalid_count = validate(&mut state.clone(), &steps);
+            println!("{}", invalid_count);
+            return;
+        }
+        Some("strict") => {
+            let invalid_count = validate(&mut state.clone(), &steps);
+            if invalid_count > 0 {
+                println!("refusing to run {} invalid instructions", invalid_count);
+                return;
+            }
+        }
+        Some("trace") => {
+            let options: Vec<String> = args.collect();
+            let crane = CustomCrane::parse(options.iter().map(String::as_str)).unwrap();
+            if let Err(e) = trace(&crane, &mut state.clone(), &steps) {
+                println!("{}", e);
+            }
+            return;
+        }
+        Some("crane") => {
+            let options: Vec<String> = args.collect();
+            let crane = CustomCrane::parse(options.iter().map(String::as_str)).unwrap();
+            let mut state = state;
+            match run(&crane, &mut state, &steps) {
+                Ok(summary) => println!(
+                    "{} ({} lifts, total time {})",
+                    top_crates(&state),
+                    summary.lifts,
+                    summary.cost
+                ),
+                Err(e) => println!("{}", e),
+            }
+            return;
+        }
+        Some("rewind") | Some("bisect") | Some("diff") => {
+            let args: Vec<String> = args.collect();
+            let position_count = if mode.as_deref() == Some("rewind") {
+                1
+            } else {
+                2
+            };
+            let (positions, options) = args.split_at(position_count.min(args.len()));
+            let crane = CustomCrane::parse(options.iter().map(String::as_str)).unwrap();
+            if let Err(e) = history(mode.as_deref().unwrap(), &crane, state, &steps, positions) {
+                println!("{}", e);
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let cranes: [&dyn Crane; 2] = [&CrateMover9000, &CrateMover9001];
+    for crane in cranes {
+        let mut state = state.clone();
+        run(crane, &mut state, &steps).unwrap();
+        println!("{}", top_crates(&state));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stacks(stacks: &[(u32, &[&str])]) -> Stacks {
+        stacks
+            .iter()
+            .map(|(id, crates)| (*id, crates.iter().map(|c| String::from(*c)).collect()))
+            .collect()
+    }
+
+    fn step(line_no: usize, count: u64, from: u32, to: u32) -> Step {
+        Step {
+            line_no,
+            inst: Inst { count, from, to },
+        }
+    }
+
+    #[test]
+    fn failed_multi_lift_move_leaves_stacks_untouched() {
+        let crane = CustomCrane {
+            max_lift: 1,
+            max_height: Some(1),
+            ..CustomCrane::default()
+        };
+        let initial = stacks(&[(1, &["C", "B", "A"]), (2, &[])]);
+        let mut state = initial.clone();
+        let result = apply(&crane, &mut state, &step(1, 3, 1, 2));
+        assert!(matches!(result, Err(InvalidStep::StackTooHigh { .. })));
+        assert_eq!(initial, state);
+    }
+
+    #[test]
+    fn undo_restores_multi_lift_move() {
+        let crane = CustomCrane {
+            max_lift: 2,
+            ..CustomCrane::default()
+        };
+        let initial = stacks(&[(1, &["D", "C", "B", "A"]), (2, &["E"])]);
+        let mut state = initial.clone();
+        let applied = apply(&crane, &mut state, &step(1, 3, 1, 2)).unwrap();
+        assert_eq!(stacks(&[(1, &["D"]), (2, &["E", "B", "A", "C"])]), state);
+        applied.undo(&mut state);
+        assert_eq!(initial, state);
+    }
+
+    #[test]
+    fn seek_round_trips() {
+        let initial = stacks(&[(1, &["Z", "N"]), (2, &["M", "C", "D"]), (3, &["P"])]);
+        let steps = vec![
+            Ok(step(1, 1, 2, 1)),
+            Ok(step(2, 3, 1, 3)),
+            Ok(step(3, 2, 2, 1)),
+            Ok(step(4, 1, 1, 2)),
+        ];
+        let mut simulation = Simulation::new(&CrateMover9000, initial.clone(), &steps);
+        let mut states = vec![initial];
+        for position in 1..=steps.len() {
+            simulation.seek(position).unwrap();
+            states.push(simulation.state.clone());
+        }
+        assert_eq!(
+            stacks(&[(1, &["C"]), (2, &["M"]), (3, &["P", "D", "N", "Z"])]),
+            states[4]
+        );
+        for position in [0, 3, 1, 4, 2, 0] {
+            simulation.seek(position).unwrap();
+            assert_eq!(position, simulation.position());
+            assert_eq!(states[position], simulation.state);
+        }
+    }
+
+    #[test]
+    fn find_first_stops_at_crates_passing_through() {
+        let initial = stacks(&[(1, &["Z", "N"]), (2, &["M", "C", "D"]), (3, &["P"])]);
+        let steps = vec![
+            Ok(step(1, 1, 2, 1)),
+            Ok(step(2, 3, 1, 3)),
+            Ok(step(3, 2, 2, 1)),
+            Ok(step(4, 1, 1, 2)),
+        ];
+        let mut simulation = Simulation::new(&CrateMover9000, initial, &steps);
+        let found = simulation.find_first(|state| state[&1].contains(&String::from("D")));
+        assert_eq!(Some(1), found.unwrap());
+        assert_eq!(1, simulation.position());
+        let found = simulation.find_first(|state| state[&2].contains(&String::from("Z")));
+        assert_eq!(None, found.unwrap());
+    }
 }
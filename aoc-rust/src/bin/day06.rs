@@ -1,59 +1,268 @@
 use std::{
+    collections::BTreeMap,
     env,
     fs::File,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, Read},
+    ops::{Range, RangeInclusive},
 };
 
-fn normalize_alphabetic_ascii(b: u8) -> usize {
-    (b - b'a') as usize
+/// Tracks the last `size` bytes of a stream along with how many distinct bytes they hold, so
+/// that each new byte is handled in constant time.
+struct MarkerDetector {
+    window: Vec<u8>,
+    histogram: [usize; 256],
+    distinct: usize,
+    offset: u64,
 }
 
-fn has_repetition(chunk: &[u8]) -> bool {
-    let mut histogram = [0usize; 64];
-    for b in chunk {
-        let count = &mut histogram[normalize_alphabetic_ascii(*b)];
+impl MarkerDetector {
+    fn new(size: usize) -> Self {
+        assert!(
+            (1..=256).contains(&size),
+            "Window size must be between 1 and 256!"
+        );
+        Self {
+            window: vec![0; size],
+            histogram: [0; 256],
+            distinct: 0,
+            offset: 0,
+        }
+    }
+
+    /// Adds a byte to the window and returns whether the window now holds `size` distinct bytes.
+    fn push(&mut self, b: u8) -> bool {
+        let size = self.window.len();
+        let slot = (self.offset % size as u64) as usize;
+        if self.offset >= size as u64 {
+            let evicted = &mut self.histogram[self.window[slot] as usize];
+            *evicted -= 1;
+            if *evicted == 0 {
+                self.distinct -= 1;
+            }
+        }
+        self.window[slot] = b;
+        let count = &mut self.histogram[b as usize];
         *count += 1;
-        if *count == 2 {
-            return true;
+        if *count == 1 {
+            self.distinct += 1;
         }
+        self.offset += 1;
+        self.distinct == size
+    }
+
+    /// Number of bytes pushed so far, which is the stream offset right after the last byte.
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn reset(&mut self) {
+        self.histogram = [0; 256];
+        self.distinct = 0;
+        self.offset = 0;
     }
-    false
 }
 
-fn part01(file: File) {
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let result = line
-            .as_bytes()
-            .windows(4)
-            .position(|chunk| !has_repetition(chunk))
-            .map(|i| i + 4);
-        if let Some(i) = result {
-            println!("{}", i);
+/// Returns the stream offset right after the first window of `size` distinct bytes.
+fn find_marker<R: Read>(reader: R, size: usize) -> std::io::Result<Option<u64>> {
+    let mut detector = MarkerDetector::new(size);
+    let mut reader = BufReader::new(reader);
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        if buf.iter().any(|b| detector.push(*b)) {
+            return Ok(Some(detector.offset()));
+        }
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
+
+/// Runs the detector over every line of the input separately, printing the offset of the first
+/// marker within each line.
+fn find_line_markers<R: Read>(reader: R, size: usize) -> std::io::Result<()> {
+    let mut detector = MarkerDetector::new(size);
+    let mut found = false;
+    let mut reader = BufReader::new(reader);
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        for b in buf {
+            if *b == b'\n' {
+                detector.reset();
+                found = false;
+            } else if !found && detector.push(*b) {
+                found = true;
+                println!("{}", detector.offset());
+            }
+        }
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
+
+/// Statistics of the runs of distinct bytes found in one signal. Runs are tracked by the offset
+/// at which the current run starts and the last offset at which every byte was seen.
+struct SignalAnalysis {
+    window_sizes: Vec<usize>,
+    last_seen: [Option<u64>; 256],
+    run_start: u64,
+    offset: u64,
+    // For every window size, the ranges of stream offsets right after an all-distinct window.
+    markers: Vec<Vec<RangeInclusive<u64>>>,
+    longest_run: Range<u64>,
+    run_lengths: BTreeMap<u64, u64>,
+}
+
+impl SignalAnalysis {
+    fn new(window_sizes: &[usize]) -> Self {
+        Self {
+            window_sizes: window_sizes.to_vec(),
+            last_seen: [None; 256],
+            run_start: 0,
+            offset: 0,
+            markers: vec![Vec::default(); window_sizes.len()],
+            longest_run: 0..0,
+            run_lengths: BTreeMap::default(),
+        }
+    }
+
+    fn push(&mut self, b: u8) {
+        if let Some(seen) = self.last_seen[b as usize].filter(|seen| *seen >= self.run_start) {
+            self.end_run();
+            self.run_start = seen + 1;
+        }
+        self.last_seen[b as usize] = Some(self.offset);
+        self.offset += 1;
+
+        let run_length = self.offset - self.run_start;
+        if run_length > self.longest_run.end - self.longest_run.start {
+            self.longest_run = self.run_start..self.offset;
+        }
+        for (size, markers) in self.window_sizes.iter().zip(self.markers.iter_mut()) {
+            if run_length < *size as u64 {
+                continue;
+            }
+            match markers.last_mut() {
+                Some(range) if *range.end() + 1 == self.offset => {
+                    *range = *range.start()..=self.offset;
+                }
+                _ => markers.push(self.offset..=self.offset),
+            }
+        }
+    }
+
+    /// Records the current run, which can not be extended any further.
+    fn end_run(&mut self) {
+        let run_length = self.offset - self.run_start;
+        if run_length > 0 {
+            *self.run_lengths.entry(run_length).or_default() += 1;
+        }
+    }
+
+    fn report(mut self, signal_no: usize) {
+        self.end_run();
+        println!("signal {}:", signal_no);
+        for (size, markers) in self.window_sizes.iter().zip(self.markers.iter()) {
+            let markers: Vec<String> = markers
+                .iter()
+                .map(|range| {
+                    if range.start() == range.end() {
+                        range.start().to_string()
+                    } else {
+                        format!("{}-{}", range.start(), range.end())
+                    }
+                })
+                .collect();
+            if markers.is_empty() {
+                println!("  window {}: none", size);
+            } else {
+                println!("  window {}: {}", size, markers.join(", "));
+            }
         }
+        println!(
+            "  longest run: {} bytes at offsets {}..{}",
+            self.longest_run.end - self.longest_run.start,
+            self.longest_run.start,
+            self.longest_run.end
+        );
+        let run_lengths: Vec<String> = self
+            .run_lengths
+            .iter()
+            .map(|(length, count)| format!("{}x{}", length, count))
+            .collect();
+        println!("  run lengths: {}", run_lengths.join(", "));
     }
 }
 
-fn part02(file: File) {
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let result = line
-            .as_bytes()
-            .windows(14)
-            .position(|chunk| !has_repetition(chunk))
-            .map(|i| i + 14);
-        if let Some(i) = result {
-            println!("{}", i);
+/// Analyses every line of the input as a separate signal.
+fn analyze<R: Read>(reader: R, window_sizes: &[usize]) -> std::io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut signal_no = 1;
+    let mut analysis = SignalAnalysis::new(window_sizes);
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
         }
+        for b in buf {
+            if *b == b'\n' {
+                let finished = std::mem::replace(&mut analysis, SignalAnalysis::new(window_sizes));
+                finished.report(signal_no);
+                signal_no += 1;
+            } else {
+                analysis.push(*b);
+            }
+        }
+        let len = buf.len();
+        reader.consume(len);
+    }
+    if analysis.offset > 0 {
+        analysis.report(signal_no);
+    }
+    Ok(())
+}
+
+fn open(fpath: &str) -> Box<dyn Read> {
+    if fpath == "-" {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(File::open(fpath).unwrap())
     }
 }
 
 fn main() {
-    let fpath = env::args()
-        .nth(1)
-        .expect("Path to input file is not given!");
-    part01(File::open(&fpath).unwrap());
-    part02(File::open(&fpath).unwrap());
+    let mut args = env::args().skip(1).peekable();
+    let fpath = args.next().expect("Path to input file is not given!");
+    let mode = args.next_if(|arg| arg == "stream" || arg == "analyze");
+    let mut window_sizes: Vec<usize> = args
+        .map(|s| s.parse().expect("Window size must be a number!"))
+        .collect();
+
+    match mode.as_deref() {
+        Some("stream") => {
+            let size = window_sizes.first().copied().unwrap_or(14);
+            match find_marker(open(&fpath), size).unwrap() {
+                Some(offset) => println!("{}", offset),
+                None => println!("no marker"),
+            }
+        }
+        Some("analyze") => {
+            if window_sizes.is_empty() {
+                window_sizes = vec![4, 14];
+            }
+            analyze(open(&fpath), &window_sizes).unwrap();
+        }
+        _ => {
+            if window_sizes.is_empty() {
+                window_sizes = vec![4, 14];
+            }
+            for size in window_sizes {
+                find_line_markers(open(&fpath), size).unwrap();
+            }
+        }
+    }
 }
@@ -1,11 +1,10 @@
 use std::{
-    cell::RefCell,
-    collections::HashMap,
+    collections::{BTreeMap, HashSet, VecDeque},
     env,
     fs::File,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, IsTerminal, Read, Write},
     iter::Peekable,
-    rc::Rc,
+    path::Path,
 };
 
 #[derive(Debug)]
@@ -16,12 +15,18 @@ struct FileDescriptor {
 
 #[derive(Debug)]
 struct FileEntry {
+    name: String,
+    parent: usize,
     size: u64,
 }
 
 impl FileEntry {
-    fn new(size: u64) -> Self {
-        Self { size }
+    fn new(name: &str, parent: usize, size: u64) -> Self {
+        Self {
+            name: String::from(name),
+            parent,
+            size,
+        }
     }
 }
 
@@ -30,152 +35,447 @@ struct DirectoryDescriptor {
     name: String,
 }
 
+/// A directory stored in the arena of a `FileSystem`. Parents and children are referred to by
+/// their index in the arena.
 #[derive(Debug)]
 struct DirectoryEntry {
-    cached_size: Option<u64>,
-    child_files: HashMap<String, FileEntry>,
-    child_directories: HashMap<String, Rc<RefCell<DirectoryEntry>>>,
+    name: String,
+    parent: Option<usize>,
+    // Total size of the files in this directory and all of its descendants.
+    size: u64,
+    child_files: BTreeMap<String, usize>,
+    child_directories: BTreeMap<String, usize>,
 }
 
 impl DirectoryEntry {
-    fn new() -> Self {
+    fn new(name: &str, parent: Option<usize>) -> Self {
         Self {
-            cached_size: None,
-            child_files: HashMap::default(),
-            child_directories: HashMap::default(),
+            name: String::from(name),
+            parent,
+            size: 0,
+            child_files: BTreeMap::default(),
+            child_directories: BTreeMap::default(),
         }
     }
+}
 
-    fn size(&mut self, use_cache: bool) -> u64 {
-        if use_cache {
-            if let Some(size) = self.cached_size {
-                return size;
-            }
-        }
-        let mut size = 0;
-        for f in self.child_files.values() {
-            size += f.size
-        }
-        for d in self.child_directories.values() {
-            let mut d = d.borrow_mut();
-            size += d.size(use_cache);
-        }
-        self.cached_size = Some(size);
-        size
+/// Visits directories parents first.
+struct PreOrderIter<'a> {
+    fs: &'a FileSystem,
+    next: Vec<usize>,
+}
+
+impl Iterator for PreOrderIter<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.next.pop()?;
+        let directory = &self.fs.directories[next];
+        self.next
+            .extend(directory.child_directories.values().rev().copied());
+        Some(next)
     }
 }
 
-struct DirectoryIter {
-    next: Vec<Rc<RefCell<DirectoryEntry>>>,
+/// Visits directories children first.
+struct PostOrderIter<'a> {
+    fs: &'a FileSystem,
+    // Each directory is paired with whether its children have already been pushed.
+    next: Vec<(usize, bool)>,
 }
 
-impl From<&FileSystem> for DirectoryIter {
-    fn from(fs: &FileSystem) -> Self {
-        Self {
-            next: vec![fs.root.clone()],
+impl Iterator for PostOrderIter<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (next, is_expanded) = self.next.pop()?;
+            if is_expanded {
+                return Some(next);
+            }
+            self.next.push((next, true));
+            let directory = &self.fs.directories[next];
+            self.next.extend(
+                directory
+                    .child_directories
+                    .values()
+                    .rev()
+                    .map(|d| (*d, false)),
+            );
         }
     }
 }
 
-impl Iterator for DirectoryIter {
-    type Item = Rc<RefCell<DirectoryEntry>>;
+/// Visits directories level by level.
+struct BreadthFirstIter<'a> {
+    fs: &'a FileSystem,
+    next: VecDeque<usize>,
+}
+
+impl Iterator for BreadthFirstIter<'_> {
+    type Item = usize;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next = self.next.pop()?;
-        {
-            let next = next.borrow();
-            self.next
-                .extend(next.child_directories.values().into_iter().cloned());
-        }
+        let next = self.next.pop_front()?;
+        let directory = &self.fs.directories[next];
+        self.next
+            .extend(directory.child_directories.values().copied());
         Some(next)
     }
 }
 
+/// A directory tree kept in an arena, where the root directory is always at index 0.
 #[derive(Debug)]
 struct FileSystem {
-    navigation: Vec<Rc<RefCell<DirectoryEntry>>>,
-    root: Rc<RefCell<DirectoryEntry>>,
+    directories: Vec<DirectoryEntry>,
+    files: Vec<FileEntry>,
+    cwd: usize,
 }
 
 impl Default for FileSystem {
     fn default() -> Self {
-        let root = Rc::new(RefCell::new(DirectoryEntry::new()));
         FileSystem {
-            navigation: vec![root.clone()],
-            root,
+            directories: vec![DirectoryEntry::new("/", None)],
+            files: Vec::default(),
+            cwd: Self::ROOT,
         }
     }
 }
 
 impl FileSystem {
+    const ROOT: usize = 0;
+
     fn update(&mut self, command: Command) -> std::io::Result<()> {
         match command.program {
             Program::Cd(directory) => self.cd(directory),
             Program::Ls => {
+                // The latest listing is the content of the directory, entries that are no longer
+                // listed have been removed since the previous listing.
+                let mut listed = HashSet::new();
                 for output in command.outputs {
                     match output {
                         ProgramOutput::File(FileDescriptor { name, size }) => {
-                            self.touch(name, *size)
+                            self.touch(self.cwd, name, *size);
+                            listed.insert(name.as_str());
+                        }
+                        ProgramOutput::Directory(DirectoryDescriptor { name }) => {
+                            self.mkdir(self.cwd, name);
+                            listed.insert(name.as_str());
                         }
-                        ProgramOutput::Directory(DirectoryDescriptor { name }) => self.mkdir(name),
                     }
                 }
+                let directory = &self.directories[self.cwd];
+                let unlisted_files: Vec<String> = directory
+                    .child_files
+                    .keys()
+                    .filter(|name| !listed.contains(name.as_str()))
+                    .cloned()
+                    .collect();
+                let unlisted_directories: Vec<String> = directory
+                    .child_directories
+                    .keys()
+                    .filter(|name| !listed.contains(name.as_str()))
+                    .cloned()
+                    .collect();
+                for name in unlisted_files {
+                    self.remove_file(self.cwd, &name);
+                }
+                for name in unlisted_directories {
+                    self.remove_directory(self.cwd, &name);
+                }
                 Ok(())
             }
-        }
-    }
-
-    fn cd(&mut self, name: &str) -> std::io::Result<()> {
-        match name {
-            "/" => {
-                self.navigation.clear();
-                self.navigation.push(self.root.clone());
+            Program::Rm { path, recursive } => {
+                let (parent, name) = self.resolve_parent(path)?;
+                if self.remove_file(parent, name).is_some() {
+                    return Ok(());
+                }
+                if !self.directories[parent]
+                    .child_directories
+                    .contains_key(name)
+                {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("no such file or directory {:?}", path),
+                    ));
+                }
+                if !recursive {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{:?} is a directory", path),
+                    ));
+                }
+                let id = self.directories[parent].child_directories[name];
+                if self.is_ancestor(id, self.cwd) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("can not remove {:?} while inside of it", path),
+                    ));
+                }
+                self.remove_directory(parent, name);
+                Ok(())
             }
-            ".." => {
-                self.navigation.pop();
+            Program::Mv { from, to } => self.mv(from, to),
+            Program::Mkdir(path) => {
+                let (parent, name) = self.resolve_parent(path)?;
+                if self.directories[parent].child_files.contains_key(name) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("{:?} is a file", path),
+                    ));
+                }
+                self.mkdir(parent, name);
+                Ok(())
             }
-            _ => {
-                let child_directory = self
-                    .navigation
-                    .last()
-                    .ok_or(std::io::Error::new(std::io::ErrorKind::Other, "corrupted"))?
-                    .borrow()
+            Program::Touch { path, size } => {
+                let (parent, name) = self.resolve_parent(path)?;
+                if self.directories[parent]
                     .child_directories
-                    .get(name)
-                    .cloned();
-                match child_directory {
-                    None => {
-                        return Err(std::io::Error::new(std::io::ErrorKind::Other, "corrupted"))
-                    }
-                    Some(directory) => self.navigation.push(directory.clone()),
+                    .contains_key(name)
+                {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("{:?} is a directory", path),
+                    ));
                 }
+                self.touch(parent, name, *size);
+                Ok(())
             }
+            // Only reads the file system, what it printed is of no use for replaying.
+            Program::Du => Ok(()),
         }
+    }
+
+    fn cd(&mut self, path: &str) -> std::io::Result<()> {
+        self.cwd = self.resolve(path).ok_or(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no such directory {:?}", path),
+        ))?;
         Ok(())
     }
 
-    fn mkdir(&mut self, name: &str) {
-        if let Some(current_directory) = self.navigation.last_mut() {
-            let mut current_directory = current_directory.borrow_mut();
-            current_directory.child_directories.insert(
-                String::from(name),
-                Rc::new(RefCell::new(DirectoryEntry::new())),
-            );
+    /// Finds the directory at the given path, relative to the current directory unless it starts
+    /// with `/`.
+    fn resolve(&self, path: &str) -> Option<usize> {
+        let mut id = if path.starts_with('/') {
+            Self::ROOT
+        } else {
+            self.cwd
+        };
+        for name in path.split('/') {
+            id = match name {
+                "" | "." => id,
+                ".." => self.directories[id].parent.unwrap_or(Self::ROOT),
+                _ => *self.directories[id].child_directories.get(name)?,
+            };
+        }
+        Some(id)
+    }
+
+    /// Splits a path into the directory holding the entry and the name of the entry.
+    fn resolve_parent<'a>(&self, path: &'a str) -> std::io::Result<(usize, &'a str)> {
+        let path = path.trim_end_matches('/');
+        let (parent_path, name) = match path.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some((parent_path, name)) => (parent_path, name),
+            None => (".", path),
+        };
+        if matches!(name, "" | "." | "..") {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("expecting the name of an entry in {:?}", path),
+            ));
+        }
+        let parent = self.resolve(parent_path).ok_or(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no such directory {:?}", parent_path),
+        ))?;
+        Ok((parent, name))
+    }
+
+    fn is_ancestor(&self, ancestor: usize, id: usize) -> bool {
+        let mut next = Some(id);
+        while let Some(id) = next {
+            if id == ancestor {
+                return true;
+            }
+            next = self.directories[id].parent;
+        }
+        false
+    }
+
+    /// Moves or renames an entry. Moving onto an existing directory puts the entry inside of it,
+    /// and moving a file onto an existing file replaces that file.
+    fn mv(&mut self, from: &str, to: &str) -> std::io::Result<()> {
+        let (from_parent, from_name) = self.resolve_parent(from)?;
+        let (to_parent, to_name) = match self.resolve(to) {
+            Some(directory) => (directory, from_name),
+            None => self.resolve_parent(to)?,
+        };
+
+        if let Some(id) = self.remove_file(from_parent, from_name) {
+            if self.directories[to_parent]
+                .child_directories
+                .contains_key(to_name)
+            {
+                self.attach_file(from_parent, from_name, id);
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("can not replace directory {:?} with a file", to),
+                ));
+            }
+            self.remove_file(to_parent, to_name);
+            self.attach_file(to_parent, to_name, id);
+            return Ok(());
+        }
+
+        let id = *self.directories[from_parent]
+            .child_directories
+            .get(from_name)
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no such file or directory {:?}", from),
+            ))?;
+        if self.is_ancestor(id, to_parent) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("can not move {:?} inside of itself", from),
+            ));
+        }
+        let directory = &self.directories[to_parent];
+        if directory.child_files.contains_key(to_name)
+            || directory.child_directories.contains_key(to_name)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{:?} already exists", to),
+            ));
+        }
+        self.remove_directory(from_parent, from_name);
+        self.attach_directory(to_parent, to_name, id);
+        Ok(())
+    }
+
+    fn mkdir(&mut self, parent: usize, name: &str) {
+        if self.directories[parent]
+            .child_directories
+            .contains_key(name)
+        {
+            return;
+        }
+        let id = self.directories.len();
+        self.directories
+            .push(DirectoryEntry::new(name, Some(parent)));
+        self.attach_directory(parent, name, id);
+    }
+
+    fn touch(&mut self, parent: usize, name: &str, size: u64) {
+        if let Some(&id) = self.directories[parent].child_files.get(name) {
+            let old_size = std::mem::replace(&mut self.files[id].size, size);
+            self.add_size(parent, size as i64 - old_size as i64);
+            return;
+        }
+        let id = self.files.len();
+        self.files.push(FileEntry::new(name, parent, size));
+        self.attach_file(parent, name, id);
+    }
+
+    fn attach_file(&mut self, parent: usize, name: &str, id: usize) {
+        let file = &mut self.files[id];
+        file.name = String::from(name);
+        file.parent = parent;
+        let size = file.size;
+        self.directories[parent]
+            .child_files
+            .insert(String::from(name), id);
+        self.add_size(parent, size as i64);
+    }
+
+    fn attach_directory(&mut self, parent: usize, name: &str, id: usize) {
+        let directory = &mut self.directories[id];
+        directory.name = String::from(name);
+        directory.parent = Some(parent);
+        let size = directory.size;
+        self.directories[parent]
+            .child_directories
+            .insert(String::from(name), id);
+        self.add_size(parent, size as i64);
+    }
+
+    /// Detaches a file from its directory and returns its index in the arena.
+    fn remove_file(&mut self, parent: usize, name: &str) -> Option<usize> {
+        let id = self.directories[parent].child_files.remove(name)?;
+        self.add_size(parent, -(self.files[id].size as i64));
+        Some(id)
+    }
+
+    /// Detaches a directory along with its content from its parent and returns its index in the
+    /// arena.
+    fn remove_directory(&mut self, parent: usize, name: &str) -> Option<usize> {
+        let id = self.directories[parent].child_directories.remove(name)?;
+        self.add_size(parent, -(self.directories[id].size as i64));
+        Some(id)
+    }
+
+    /// Applies a change in size to a directory and all of its ancestors.
+    fn add_size(&mut self, id: usize, delta: i64) {
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let directory = &mut self.directories[id];
+            directory.size = directory.size.checked_add_signed(delta).unwrap();
+            next = directory.parent;
         }
     }
 
-    fn touch(&mut self, name: &str, size: u64) {
-        if let Some(current_directory) = self.navigation.last_mut() {
-            let mut current_directory = current_directory.borrow_mut();
-            current_directory
-                .child_files
-                .insert(String::from(name), FileEntry::new(size));
+    fn directory_size(&self, id: usize) -> u64 {
+        self.directories[id].size
+    }
+
+    fn size(&self) -> u64 {
+        self.directory_size(Self::ROOT)
+    }
+
+    fn file_path(&self, id: usize) -> String {
+        let file = &self.files[id];
+        match file.parent {
+            Self::ROOT => format!("/{}", file.name),
+            parent => format!("{}/{}", self.path(parent), file.name),
+        }
+    }
+
+    fn path(&self, id: usize) -> String {
+        let mut names = Vec::default();
+        let mut next = Some(id);
+        while let Some(id) = next {
+            let directory = &self.directories[id];
+            if directory.parent.is_some() {
+                names.push(directory.name.as_str());
+            }
+            next = directory.parent;
+        }
+        names.reverse();
+        format!("/{}", names.join("/"))
+    }
+
+    fn pre_order(&self, id: usize) -> PreOrderIter<'_> {
+        PreOrderIter {
+            fs: self,
+            next: vec![id],
         }
     }
 
-    fn size(&self, use_cache: bool) -> u64 {
-        let mut root = self.root.borrow_mut();
-        root.size(use_cache)
+    fn post_order(&self, id: usize) -> PostOrderIter<'_> {
+        PostOrderIter {
+            fs: self,
+            next: vec![(id, false)],
+        }
+    }
+
+    fn breadth_first(&self, id: usize) -> BreadthFirstIter<'_> {
+        BreadthFirstIter {
+            fs: self,
+            next: VecDeque::from([id]),
+        }
     }
 }
 
@@ -183,6 +483,11 @@ impl FileSystem {
 enum Program {
     Cd(String),
     Ls,
+    Rm { path: String, recursive: bool },
+    Mv { from: String, to: String },
+    Mkdir(String),
+    Touch { path: String, size: u64 },
+    Du,
 }
 
 impl Program {
@@ -196,20 +501,43 @@ impl Program {
         ))?;
         assert_eq!("$", leading_token);
 
-        match tokens.next() {
-            Some("cd") => {
-                tokens
-                    .next()
-                    .map(|p| Program::Cd(String::from(p)))
-                    .ok_or(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "expecting 1 argument",
-                    ))
-            }
-            Some("ls") => Ok(Program::Ls),
-            _ => Err(std::io::Error::new(
+        let mut argument = || {
+            tokens.next().map(String::from).ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "expecting 1 more argument",
+            ))
+        };
+        match argument()?.as_str() {
+            "cd" => Ok(Program::Cd(argument()?)),
+            "ls" => Ok(Program::Ls),
+            "rm" => match argument()?.as_str() {
+                "-r" => Ok(Program::Rm {
+                    path: argument()?,
+                    recursive: true,
+                }),
+                path => Ok(Program::Rm {
+                    path: String::from(path),
+                    recursive: false,
+                }),
+            },
+            "mv" => Ok(Program::Mv {
+                from: argument()?,
+                to: argument()?,
+            }),
+            "mkdir" => Ok(Program::Mkdir(argument()?)),
+            "touch" => {
+                let size = argument()?
+                    .parse()
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                Ok(Program::Touch {
+                    path: argument()?,
+                    size,
+                })
+            }
+            "du" => Ok(Program::Du),
+            name => Err(std::io::Error::new(
                 std::io::ErrorKind::Unsupported,
-                "program does not exist",
+                format!("program {:?} does not exist", name),
             )),
         }
     }
@@ -227,33 +555,27 @@ impl ProgramOutput {
         T: Iterator<Item = &'a str>,
     {
         match tokens.next() {
-            Some("dir") => tokens
-                .next()
-                .map(|p| {
-                    ProgramOutput::Directory(DirectoryDescriptor {
-                        name: String::from(p),
-                    })
-                })
-                .ok_or(std::io::Error::new(
+            Some("dir") => {
+                let name = tokens.next().ok_or(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
                     "expecting directory's name",
-                )),
+                ))?;
+                Ok(ProgramOutput::Directory(DirectoryDescriptor {
+                    name: Self::entry_name(name)?,
+                }))
+            }
             Some(s) => {
                 let file_size = s
                     .parse::<u64>()
                     .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-                tokens
-                    .next()
-                    .map(|p| {
-                        ProgramOutput::File(FileDescriptor {
-                            name: String::from(p),
-                            size: file_size,
-                        })
-                    })
-                    .ok_or(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "expecting directory's name",
-                    ))
+                let name = tokens.next().ok_or(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "expecting file's name",
+                ))?;
+                Ok(ProgramOutput::File(FileDescriptor {
+                    name: Self::entry_name(name)?,
+                    size: file_size,
+                }))
             }
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
@@ -261,6 +583,17 @@ impl ProgramOutput {
             )),
         }
     }
+
+    /// Checks that a listed name stays within the listed directory.
+    fn entry_name(name: &str) -> std::io::Result<String> {
+        if matches!(name, "" | "." | "..") || name.contains('/') {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidFilename,
+                format!("{:?} is not the name of an entry", name),
+            ));
+        }
+        Ok(String::from(name))
+    }
 }
 
 struct Command<'a> {
@@ -274,63 +607,1153 @@ impl<'a> Command<'a> {
     }
 }
 
-fn main() {
-    let fpath = env::args()
-        .nth(1)
-        .expect("Path to input file is not given!");
+/// Replays a terminal transcript onto the file system. Unless `is_strict` is set, unknown
+/// programs are skipped along with their outputs, as are outputs that can not be parsed. Listed
+/// names that would lead out of their directory are always an error. The output of `du` is
+/// always skipped, since it only reports on the file system.
+fn replay<R: BufRead>(
+    reader: R,
+    file_system: &mut FileSystem,
+    is_strict: bool,
+) -> std::io::Result<()> {
+    let with_line_no = |line_no: usize, e: std::io::Error| {
+        std::io::Error::new(e.kind(), format!("line {}: {}", line_no, e))
+    };
 
-    let mut file_system = FileSystem::default();
-    let mut current_program = None;
+    // The program being run is kept along with the line it starts on, while outputs are skipped
+    // whenever the program is unknown.
+    let mut current_program: Option<(usize, Program)> = None;
     let mut current_outputs = Vec::default();
+    let mut is_skipping = false;
 
-    let reader = BufReader::new(File::open(&fpath).unwrap());
-    for line in reader.lines() {
-        let line = line.unwrap();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = idx + 1;
         let mut tokens = line.split_whitespace().peekable();
-        while let Some(token) = tokens.peek() {
-            match token {
-                &"$" => {
-                    if let Some(ref program) = current_program {
-                        let command = Command::new(program, &current_outputs);
-                        file_system.update(command).unwrap();
+        if tokens.peek() == Some(&"$") {
+            if let Some((program_line_no, program)) = current_program.take() {
+                let command = Command::new(&program, &current_outputs);
+                file_system
+                    .update(command)
+                    .map_err(|e| with_line_no(program_line_no, e))?;
+            }
+            current_outputs.clear();
+            match Program::parse(&mut tokens) {
+                Ok(program) => {
+                    is_skipping = matches!(program, Program::Du);
+                    current_program = Some((line_no, program));
+                }
+                Err(e) if is_strict || e.kind() != std::io::ErrorKind::Unsupported => {
+                    return Err(with_line_no(line_no, e));
+                }
+                Err(_) => is_skipping = true,
+            }
+        } else if !is_skipping && tokens.peek().is_some() {
+            match ProgramOutput::parse(&mut tokens) {
+                Ok(output) => current_outputs.push(output),
+                Err(e) if is_strict || e.kind() == std::io::ErrorKind::InvalidFilename => {
+                    return Err(with_line_no(line_no, e));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    if let Some((program_line_no, program)) = current_program {
+        let command = Command::new(&program, &current_outputs);
+        file_system
+            .update(command)
+            .map_err(|e| with_line_no(program_line_no, e))?;
+    }
+    Ok(())
+}
+
+fn human_readable(size: u64) -> String {
+    let mut value = size as f64;
+    for unit in ["", "K", "M", "G", "T"] {
+        if value < 1024.0 || unit == "T" {
+            return if unit.is_empty() {
+                size.to_string()
+            } else if value < 10.0 {
+                format!("{:.1}{}", value, unit)
+            } else {
+                format!("{:.0}{}", value, unit)
+            };
+        }
+        value /= 1024.0;
+    }
+    unreachable!()
+}
+
+fn parse_size(s: &str) -> std::io::Result<u64> {
+    let (digits, multiplier) = match s.chars().last() {
+        Some('k' | 'K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .map(|n| n * multiplier)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+/// An interactive shell for exploring a file system that was rebuilt from a transcript.
+struct Shell<'a> {
+    fs: &'a mut FileSystem,
+}
+
+impl Shell<'_> {
+    fn resolve_or_cwd(&self, path: Option<&str>) -> std::io::Result<usize> {
+        match path {
+            None => Ok(self.fs.cwd),
+            Some(path) => self.fs.resolve(path).ok_or(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no such directory {:?}", path),
+            )),
+        }
+    }
+
+    fn run(&mut self, line: &str) -> std::io::Result<bool> {
+        let mut tokens = line.split_whitespace();
+        let Some(program) = tokens.next() else {
+            return Ok(true);
+        };
+        let args: Vec<&str> = tokens.collect();
+        match program {
+            "exit" | "quit" => return Ok(false),
+            "help" => println!(
+                "cd <dir> | ls [-l] [dir] | pwd | tree [dir] | du [-h] [--max-depth N] [dir] \
+                 | find [dir] [-size [+|-]N[k|M|G]] [-type f|d] | sort [-r] | exit"
+            ),
+            "cd" => self.fs.cd(args.first().copied().unwrap_or("/"))?,
+            "pwd" => println!("{}", self.fs.path(self.fs.cwd)),
+            "ls" => {
+                let is_long = args.contains(&"-l");
+                let path = args.iter().find(|arg| !arg.starts_with('-')).copied();
+                let directory = &self.fs.directories[self.resolve_or_cwd(path)?];
+                for (name, &id) in &directory.child_directories {
+                    if is_long {
+                        println!("dir  {:>12} {}/", self.fs.directory_size(id), name);
+                    } else {
+                        println!("{}/", name);
                     }
-                    if let Ok(program) = Program::parse(&mut tokens) {
-                        current_program.replace(program);
-                        current_outputs.clear();
+                }
+                for (name, &id) in &directory.child_files {
+                    if is_long {
+                        println!("file {:>12} {}", self.fs.files[id].size, name);
+                    } else {
+                        println!("{}", name);
+                    }
+                }
+            }
+            "tree" => {
+                let root = self.resolve_or_cwd(args.first().copied())?;
+                self.print_tree(root, 0);
+            }
+            "du" => {
+                let mut is_human = false;
+                let mut max_depth = usize::MAX;
+                let mut path = None;
+                let mut args = args.iter();
+                while let Some(arg) = args.next() {
+                    match *arg {
+                        "-h" => is_human = true,
+                        "--max-depth" | "-d" => {
+                            max_depth =
+                                parse_size(args.next().copied().unwrap_or_default())? as usize
+                        }
+                        _ => match arg.strip_prefix("--max-depth=") {
+                            Some(depth) => max_depth = parse_size(depth)? as usize,
+                            None => path = Some(*arg),
+                        },
                     }
                 }
-                _ => {
-                    if let Ok(output) = ProgramOutput::parse(&mut tokens) {
-                        current_outputs.push(output);
+                let root = self.resolve_or_cwd(path)?;
+                self.print_du(root, 0, max_depth, is_human);
+            }
+            "find" => {
+                let mut root = None;
+                let mut size_filter: Option<(std::cmp::Ordering, u64)> = None;
+                let mut type_filter = None;
+                let mut args = args.iter();
+                while let Some(arg) = args.next() {
+                    match *arg {
+                        "-size" => {
+                            let size = args.next().copied().unwrap_or_default();
+                            size_filter = Some(match size.as_bytes().first() {
+                                Some(b'+') => {
+                                    (std::cmp::Ordering::Greater, parse_size(&size[1..])?)
+                                }
+                                Some(b'-') => (std::cmp::Ordering::Less, parse_size(&size[1..])?),
+                                _ => (std::cmp::Ordering::Equal, parse_size(size)?),
+                            });
+                        }
+                        "-type" => type_filter = args.next().copied(),
+                        _ => root = Some(*arg),
+                    }
+                }
+                let root = self.resolve_or_cwd(root)?;
+                let matches_size =
+                    |size: u64| size_filter.is_none_or(|(ordering, n)| size.cmp(&n) == ordering);
+                for directory in self.fs.pre_order(root) {
+                    if type_filter != Some("f") && matches_size(self.fs.directory_size(directory)) {
+                        println!("{}", self.fs.path(directory));
+                    }
+                    if type_filter == Some("d") {
+                        continue;
                     }
+                    for &file in self.fs.directories[directory].child_files.values() {
+                        if matches_size(self.fs.files[file].size) {
+                            println!("{}", self.fs.file_path(file));
+                        }
+                    }
+                }
+            }
+            "sort" => {
+                let mut directories: Vec<usize> = self.fs.pre_order(FileSystem::ROOT).collect();
+                directories.sort_by_key(|d| std::cmp::Reverse(self.fs.directory_size(*d)));
+                if args.contains(&"-r") {
+                    directories.reverse();
+                }
+                for directory in directories {
+                    println!(
+                        "{:>12} {}",
+                        self.fs.directory_size(directory),
+                        self.fs.path(directory)
+                    );
                 }
             }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!("program {:?} does not exist", program),
+                ))
+            }
+        }
+        Ok(true)
+    }
+
+    fn print_tree(&self, id: usize, depth: usize) {
+        let directory = &self.fs.directories[id];
+        let indent = "  ".repeat(depth);
+        println!(
+            "{}- {} (dir, size={})",
+            indent,
+            directory.name,
+            self.fs.directory_size(id)
+        );
+        let mut entries: Vec<(&String, Option<usize>, Option<usize>)> = directory
+            .child_directories
+            .iter()
+            .map(|(name, d)| (name, Some(*d), None))
+            .chain(
+                directory
+                    .child_files
+                    .iter()
+                    .map(|(name, f)| (name, None, Some(*f))),
+            )
+            .collect();
+        entries.sort_by_key(|(name, _, _)| *name);
+        for (name, d, f) in entries {
+            match (d, f) {
+                (Some(d), _) => self.print_tree(d, depth + 1),
+                (_, Some(f)) => println!(
+                    "{}  - {} (file, size={})",
+                    indent, name, self.fs.files[f].size
+                ),
+                _ => unreachable!(),
+            }
         }
     }
 
-    if let Some(ref program) = current_program {
-        let command = Command::new(program, &current_outputs);
-        file_system.update(command).unwrap();
+    fn print_du(&self, id: usize, depth: usize, max_depth: usize, is_human: bool) {
+        for &child in self.fs.directories[id].child_directories.values() {
+            self.print_du(child, depth + 1, max_depth, is_human);
+        }
+        if depth <= max_depth {
+            let size = self.fs.directory_size(id);
+            let size = if is_human {
+                human_readable(size)
+            } else {
+                size.to_string()
+            };
+            println!("{}\t{}", size, self.fs.path(id));
+        }
+    }
+
+    /// Lists the paths of directories that can complete the last word of a line.
+    fn complete(&self, word: &str) -> Vec<String> {
+        let (parent_path, prefix) = match word.rsplit_once('/') {
+            Some((parent_path, prefix)) => (Some(format!("{}/", parent_path)), prefix),
+            None => (None, word),
+        };
+        let Some(parent) = self.fs.resolve(parent_path.as_deref().unwrap_or(".")) else {
+            return Vec::default();
+        };
+        self.fs.directories[parent]
+            .child_directories
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| format!("{}{}/", parent_path.as_deref().unwrap_or(""), name))
+            .collect()
+    }
+}
+
+/// Puts the terminal in non-canonical mode so that keys such as tab are read as they are typed,
+/// restoring the original settings when dropped.
+struct RawTerminal {
+    settings: String,
+}
+
+impl RawTerminal {
+    fn stty(args: &[&str]) -> std::io::Result<String> {
+        let output = std::process::Command::new("stty")
+            .args(args)
+            .stdin(std::process::Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other("stty failed"));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn new() -> std::io::Result<Self> {
+        let settings = Self::stty(&["-g"])?;
+        Self::stty(&["-icanon", "-echo", "min", "1"])?;
+        Ok(Self { settings })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = Self::stty(&[&self.settings]);
+    }
+}
+
+/// Reads a line from a raw terminal, completing directory names on tab. Returns `None` at the
+/// end of the input.
+fn read_line_completing<R: Read>(
+    input: &mut R,
+    shell: &Shell,
+    prompt: &str,
+) -> std::io::Result<Option<String>> {
+    let mut stdout = std::io::stdout();
+    let mut line = String::default();
+    let mut byte = [0u8; 1];
+    loop {
+        if input.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            b'\n' | b'\r' => {
+                println!();
+                return Ok(Some(line));
+            }
+            // Ctrl-D
+            4 if line.is_empty() => return Ok(None),
+            // Backspace and delete
+            8 | 127 if !line.is_empty() => {
+                line.pop();
+                print!("\x08 \x08");
+            }
+            b'\t' => {
+                let word_start = line.rfind(' ').map_or(0, |i| i + 1);
+                let candidates = shell.complete(&line[word_start..]);
+                let common = candidates.iter().skip(1).fold(
+                    candidates.first().cloned().unwrap_or_default(),
+                    |common, candidate| {
+                        let len = common
+                            .chars()
+                            .zip(candidate.chars())
+                            .take_while(|(a, b)| a == b)
+                            .count();
+                        common.chars().take(len).collect()
+                    },
+                );
+                if candidates.len() > 1 {
+                    println!();
+                    println!("{}", candidates.join("  "));
+                    print!("{}{}", prompt, line);
+                }
+                if common.len() > line.len() - word_start {
+                    print!("{}", &common[line.len() - word_start..]);
+                    line.replace_range(word_start.., &common);
+                }
+            }
+            b if b.is_ascii_graphic() || b == b' ' => {
+                line.push(b as char);
+                print!("{}", b as char);
+            }
+            _ => {}
+        }
+        stdout.flush()?;
+    }
+}
+
+fn shell(file_system: &mut FileSystem) -> std::io::Result<()> {
+    let mut shell = Shell { fs: file_system };
+    // Tab completion needs the keys as they are typed, which only works on a terminal.
+    let raw_terminal = if std::io::stdin().is_terminal() {
+        RawTerminal::new().ok()
+    } else {
+        None
+    };
+    let mut input = std::io::stdin().lock();
+    loop {
+        let prompt = format!("{} $ ", shell.fs.path(shell.fs.cwd));
+        print!("{}", prompt);
+        std::io::stdout().flush()?;
+        let line = match raw_terminal {
+            Some(_) => read_line_completing(&mut input, &shell, &prompt)?,
+            None => {
+                let mut line = String::default();
+                match input.read_line(&mut line)? {
+                    0 => None,
+                    _ => Some(line),
+                }
+            }
+        };
+        let Some(line) = line else {
+            println!();
+            return Ok(());
+        };
+        match shell.run(&line) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Quotes a name as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes the subtree as nested JSON objects, where directories list their children and carry
+/// their recursive size.
+fn export_json<W: Write>(
+    fs: &FileSystem,
+    id: usize,
+    depth: usize,
+    w: &mut W,
+) -> std::io::Result<()> {
+    let directory = &fs.directories[id];
+    let indent = "  ".repeat(depth);
+    writeln!(w, "{}{{", indent)?;
+    writeln!(w, "{}  \"name\": {},", indent, json_string(&directory.name))?;
+    writeln!(w, "{}  \"type\": \"directory\",", indent)?;
+    writeln!(w, "{}  \"path\": {},", indent, json_string(&fs.path(id)))?;
+    writeln!(w, "{}  \"size\": {},", indent, fs.directory_size(id))?;
+    write!(w, "{}  \"children\": [", indent)?;
+    let mut is_first = true;
+    for &child in directory.child_directories.values() {
+        writeln!(w, "{}", if is_first { "" } else { "," })?;
+        export_json(fs, child, depth + 2, w)?;
+        is_first = false;
+    }
+    for &file in directory.child_files.values() {
+        let file_entry = &fs.files[file];
+        writeln!(w, "{}", if is_first { "" } else { "," })?;
+        write!(
+            w,
+            "{}    {{\"name\": {}, \"type\": \"file\", \"path\": {}, \"size\": {}}}",
+            indent,
+            json_string(&file_entry.name),
+            json_string(&fs.file_path(file)),
+            file_entry.size
+        )?;
+        is_first = false;
+    }
+    if !is_first {
+        write!(w, "\n{}  ", indent)?;
+    }
+    writeln!(w, "]")?;
+    write!(w, "{}}}", indent)?;
+    if depth == 0 {
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/// Writes every file and directory the way `du -ab` does, with children listed before their
+/// parent directory.
+fn export_du<W: Write>(fs: &FileSystem, w: &mut W) -> std::io::Result<()> {
+    for directory in fs.post_order(FileSystem::ROOT) {
+        for &file in fs.directories[directory].child_files.values() {
+            writeln!(w, "{}\t{}", fs.files[file].size, fs.file_path(file))?;
+        }
+        writeln!(
+            w,
+            "{}\t{}",
+            fs.directory_size(directory),
+            fs.path(directory)
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the subtree in the format of `ncdu -o`, which can be browsed with `ncdu -f`. A
+/// directory is an array whose first element describes the directory itself, followed by its
+/// children. Since ncdu sums up the sizes on its own, directories are given no size of their
+/// own, and their recursive size is kept under an extra key that ncdu ignores.
+fn export_ncdu<W: Write>(fs: &FileSystem, w: &mut W) -> std::io::Result<()> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    write!(
+        w,
+        "[1,2,{{\"progname\":\"aoc-rust\",\"progver\":\"{}\",\"timestamp\":{}}},",
+        env!("CARGO_PKG_VERSION"),
+        timestamp
+    )?;
+    export_ncdu_directory(fs, FileSystem::ROOT, w)?;
+    writeln!(w, "]")
+}
+
+fn export_ncdu_directory<W: Write>(fs: &FileSystem, id: usize, w: &mut W) -> std::io::Result<()> {
+    let directory = &fs.directories[id];
+    write!(
+        w,
+        "\n[{{\"name\":{},\"asize\":0,\"dsize\":0,\"recursive_size\":{}}}",
+        json_string(if id == FileSystem::ROOT {
+            "/"
+        } else {
+            &directory.name
+        }),
+        fs.directory_size(id)
+    )?;
+    for &child in directory.child_directories.values() {
+        write!(w, ",")?;
+        export_ncdu_directory(fs, child, w)?;
+    }
+    for &file in directory.child_files.values() {
+        let file_entry = &fs.files[file];
+        write!(
+            w,
+            ",\n{{\"name\":{},\"asize\":{},\"dsize\":{}}}",
+            json_string(&file_entry.name),
+            file_entry.size,
+            file_entry.size
+        )?;
+    }
+    write!(w, "]")
+}
+
+/// Creates the subtree under `root`, using sparse files of the recorded sizes so that no data
+/// has to be written.
+fn materialize(fs: &FileSystem, root: &Path) -> std::io::Result<()> {
+    for directory in fs.pre_order(FileSystem::ROOT) {
+        let dir_path = root.join(fs.path(directory).trim_start_matches('/'));
+        std::fs::create_dir_all(&dir_path)?;
+        for (name, &file) in &fs.directories[directory].child_files {
+            File::create(dir_path.join(name))?.set_len(fs.files[file].size)?;
+        }
+    }
+    Ok(())
+}
+
+/// Walks a real directory and writes a transcript of `cd` and `ls` commands exploring it.
+/// Symbolic links and special files are left out.
+fn capture<W: Write>(root: &Path, w: &mut W) -> std::io::Result<()> {
+    writeln!(w, "$ cd /")?;
+    capture_directory(root, w)
+}
+
+fn capture_directory<W: Write>(dir_path: &Path, w: &mut W) -> std::io::Result<()> {
+    let mut files = BTreeMap::default();
+    let mut directories = BTreeMap::default();
+    for entry in std::fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().map_err(|name| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("name {:?} is not valid UTF-8", name),
+            )
+        })?;
+        if name.contains(char::is_whitespace) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("name {:?} can not be written to a transcript", name),
+            ));
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            directories.insert(name, entry.path());
+        } else if file_type.is_file() {
+            files.insert(name, entry.metadata()?.len());
+        }
+    }
+
+    writeln!(w, "$ ls")?;
+    for name in directories.keys() {
+        writeln!(w, "dir {}", name)?;
+    }
+    for (name, size) in &files {
+        writeln!(w, "{} {}", size, name)?;
+    }
+    for (name, path) in &directories {
+        writeln!(w, "$ cd {}", name)?;
+        capture_directory(path, w)?;
+        writeln!(w, "$ cd ..")?;
+    }
+    Ok(())
+}
+
+/// Sums up the sizes of the directories themselves within the subtree, which `du` counts on top
+/// of the sizes of the files.
+fn directory_overheads(
+    fs: &FileSystem,
+    root: &Path,
+    id: usize,
+    overheads: &mut BTreeMap<usize, u64>,
+) -> std::io::Result<u64> {
+    let mut overhead = std::fs::metadata(root.join(fs.path(id).trim_start_matches('/')))?.len();
+    for &child in fs.directories[id].child_directories.values() {
+        overhead += directory_overheads(fs, root, child, overheads)?;
+    }
+    overheads.insert(id, overhead);
+    Ok(overhead)
+}
+
+/// Materializes the file system in a temporary directory, then checks every size against
+/// `du --apparent-size` and checks that capturing the directory gives back the same tree.
+fn verify(fs: &FileSystem) -> std::io::Result<()> {
+    let root = env::temp_dir().join(format!("day07-{}", std::process::id()));
+    if root.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", root.display()),
+        ));
+    }
+    let result = materialize(fs, &root).and_then(|_| verify_materialized(fs, &root));
+    std::fs::remove_dir_all(&root)?;
+    result
+}
+
+fn verify_materialized(fs: &FileSystem, root: &Path) -> std::io::Result<()> {
+    let output = std::process::Command::new("du")
+        .args(["--apparent-size", "--block-size=1", "--all"])
+        .arg(root)
+        .output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other("du failed"));
+    }
+    let mut du_sizes = BTreeMap::default();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let (size, path) = line.split_once('\t').ok_or(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unexpected du output {:?}", line),
+        ))?;
+        let size: u64 = size
+            .parse()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let path = Path::new(path)
+            .strip_prefix(root)
+            .unwrap_or(Path::new(path));
+        du_sizes.insert(format!("/{}", path.display()), size);
+    }
+
+    let mut overheads = BTreeMap::default();
+    directory_overheads(fs, root, FileSystem::ROOT, &mut overheads)?;
+    let mut entries = Vec::default();
+    for directory in fs.pre_order(FileSystem::ROOT) {
+        entries.push((
+            fs.path(directory),
+            fs.directory_size(directory),
+            overheads[&directory],
+        ));
+        for &file in fs.directories[directory].child_files.values() {
+            entries.push((fs.file_path(file), fs.files[file].size, 0));
+        }
+    }
+    let mut mismatches = 0;
+    for (path, size, overhead) in &entries {
+        match du_sizes.get(path) {
+            Some(du_size) if *du_size == size + overhead => {}
+            Some(du_size) => {
+                mismatches += 1;
+                println!(
+                    "{}: size {}, du reports {} ({} for directories)",
+                    path, size, du_size, overhead
+                );
+            }
+            None => {
+                mismatches += 1;
+                println!("{}: missing from du", path);
+            }
+        }
+    }
+    println!(
+        "du: {} entries checked, {} mismatched, total size {}",
+        entries.len(),
+        mismatches,
+        fs.size()
+    );
+
+    let mut original = Vec::default();
+    export_du(fs, &mut original)?;
+    let mut transcript = Vec::default();
+    capture(root, &mut transcript)?;
+    let mut captured_fs = FileSystem::default();
+    replay(transcript.as_slice(), &mut captured_fs, true)?;
+    let mut captured = Vec::default();
+    export_du(&captured_fs, &mut captured)?;
+    let is_identical = original == captured;
+    println!(
+        "capture: {} transcript lines, {}",
+        transcript.iter().filter(|b| **b == b'\n').count(),
+        if is_identical {
+            "tree is identical"
+        } else {
+            "tree differs"
+        }
+    );
+
+    if mismatches > 0 || !is_identical {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "materialized tree does not match",
+        ));
+    }
+    Ok(())
+}
+
+const DISK_SIZE: u64 = 70000000;
+const REQUIRED_SPACE: u64 = 30000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Objective {
+    Bytes,
+    Count,
+}
+
+/// Finds the largest total size that can be freed by deleting at most `c` non-nested directories
+/// within the subtree, for every `c`.
+fn most_freeable_sizes(fs: &FileSystem, id: usize) -> Vec<u64> {
+    let mut sizes = vec![0];
+    for &child in fs.directories[id].child_directories.values() {
+        let child_sizes = most_freeable_sizes(fs, child);
+        let mut next_sizes = vec![0; sizes.len() + child_sizes.len() - 1];
+        for (count, size) in sizes.iter().enumerate() {
+            for (child_count, child_size) in child_sizes.iter().enumerate() {
+                let next = &mut next_sizes[count + child_count];
+                *next = (*next).max(size + child_size);
+            }
+        }
+        sizes = next_sizes;
+    }
+    if sizes.len() < 2 {
+        sizes.push(0);
+    }
+    sizes[1] = sizes[1].max(fs.directory_size(id));
+    // A plan deleting `c` directories also counts as a plan for `c + 1` directories.
+    for c in 1..sizes.len() {
+        sizes[c] = sizes[c].max(sizes[c - 1]);
+    }
+    sizes
+}
+
+/// Knapsack over the directory tree. Directories are visited children first, and the sizes
+/// reachable so far are threaded through the subtrees in order. When a directory is finished,
+/// deleting it is combined with the sizes that were reachable before entering it, which only
+/// use directories outside its subtree. Those are recovered by removing the sizes first reached
+/// within the subtree, so a single set of sizes below the target is kept per layer. A size at or
+/// above the target can only grow when combined further, so it is recorded as a candidate plan
+/// instead of being kept. Each directory takes time proportional to the target in words, and
+/// memory is two bits per size below the target and layer, plus a word for every one of those
+/// sizes that can be freed.
+struct Knapsack<'a> {
+    fs: &'a FileSystem,
+    target: u64,
+    // The most directories a plan may delete, or 0 to minimise bytes without a limit.
+    max_count: usize,
+    // Bitsets of the sizes below the target that can be freed, by the number of directories
+    // deleted when minimising the count.
+    reachable: Vec<Vec<u64>>,
+    // The bitsets as they were when entering the directory being finished.
+    before: Vec<Vec<u64>>,
+    // Finished directories, with the layers and sizes first reached by deleting them, as sorted
+    // keys of `count * target + size`.
+    finished: Vec<(usize, Vec<u64>)>,
+    // The smallest size of at least the target, the directory deleted last to free it, and the
+    // layer and size reachable before deleting that directory.
+    best: Option<(u64, usize, (usize, u64))>,
+}
+
+impl<'a> Knapsack<'a> {
+    fn new(fs: &'a FileSystem, target: u64, max_count: usize) -> Self {
+        let words = target.div_ceil(64) as usize;
+        let mut reachable = vec![vec![0; words]; max_count + 1];
+        reachable[0][0] = 1;
+        Self {
+            fs,
+            target,
+            max_count,
+            reachable,
+            before: Vec::default(),
+            finished: Vec::default(),
+            best: None,
+        }
+    }
+
+    /// The layer that deleting one more directory on top of layer `count` lands in.
+    fn next_layer(&self, count: usize) -> Option<usize> {
+        if self.max_count == 0 {
+            Some(0)
+        } else {
+            Some(count + 1).filter(|c| *c <= self.max_count)
+        }
+    }
+
+    fn visit(&mut self, id: usize) {
+        let entered = self.finished.len();
+        for &child in self.fs.directories[id].child_directories.values() {
+            self.visit(child);
+        }
+        let size = self.fs.directory_size(id);
+        let mut reached = Vec::default();
+        if size == 0 {
+            self.finished.push((id, reached));
+            return;
+        }
+
+        self.before.clone_from(&self.reachable);
+        for (_, sizes) in &self.finished[entered..] {
+            for &key in sizes {
+                let (count, freed) = ((key / self.target) as usize, key % self.target);
+                self.before[count][freed as usize / 64] &= !(1 << (freed % 64));
+            }
+        }
+        for count in 0..self.before.len() {
+            let Some(next) = self.next_layer(count) else {
+                continue;
+            };
+            let before = &self.before[count];
+            let smallest = self.target.saturating_sub(size);
+            if let Some(freed) = first_set_bit(before, smallest) {
+                if self.best.is_none_or(|(best, ..)| freed + size < best) {
+                    self.best = Some((freed + size, id, (count, freed)));
+                }
+            }
+
+            let (shift_words, shift_bits) = ((size / 64) as usize, size % 64);
+            let reachable = &mut self.reachable[next];
+            for word in shift_words..reachable.len() {
+                let mut shifted = before[word - shift_words] << shift_bits;
+                if shift_bits > 0 && word > shift_words {
+                    shifted |= before[word - shift_words - 1] >> (64 - shift_bits);
+                }
+                if word == reachable.len() - 1 && self.target % 64 > 0 {
+                    shifted &= (1 << (self.target % 64)) - 1;
+                }
+                let mut added = shifted & !reachable[word];
+                reachable[word] |= added;
+                while added != 0 {
+                    let bit = added.trailing_zeros() as u64;
+                    reached.push(next as u64 * self.target + word as u64 * 64 + bit);
+                    added &= added - 1;
+                }
+            }
+        }
+        self.finished.push((id, reached));
+    }
+
+    /// The directories deleted to free exactly `size`, reachable in layer `count`.
+    fn rebuild(&self, (mut count, mut size): (usize, u64), deleted: &mut Vec<usize>) {
+        while size > 0 {
+            let &(id, _) = self
+                .finished
+                .iter()
+                .find(|(_, sizes)| {
+                    sizes
+                        .binary_search(&(count as u64 * self.target + size))
+                        .is_ok()
+                })
+                .expect("size is reachable");
+            deleted.push(id);
+            size -= self.fs.directory_size(id);
+            count = count.saturating_sub(1);
+        }
+    }
+
+    /// Finds the smallest size of at least the target, and the directories that free it.
+    fn solve(&mut self) -> Option<Vec<usize>> {
+        self.visit(FileSystem::ROOT);
+        let (_, id, rest) = self.best?;
+        let mut deleted = vec![id];
+        self.rebuild(rest, &mut deleted);
+        Some(deleted)
+    }
+}
+
+/// The smallest set bit in `bits` that is at least `from`.
+fn first_set_bit(bits: &[u64], from: u64) -> Option<u64> {
+    let start = (from / 64) as usize;
+    let first = bits.get(start)? & (u64::MAX << (from % 64));
+    if first != 0 {
+        return Some(start as u64 * 64 + first.trailing_zeros() as u64);
+    }
+    bits[start + 1..]
+        .iter()
+        .position(|word| *word != 0)
+        .map(|pos| (start + 1 + pos) as u64 * 64 + bits[start + 1 + pos].trailing_zeros() as u64)
+}
+
+/// Picks non-nested directories to delete so that at least `required_space` bytes are free on a
+/// disk of `disk_size` bytes. Returns `None` if even deleting everything is not enough.
+fn plan_deletion(
+    fs: &FileSystem,
+    disk_size: u64,
+    required_space: u64,
+    objective: Objective,
+) -> Option<Vec<usize>> {
+    let free_space = disk_size.saturating_sub(fs.size());
+    let target = required_space.saturating_sub(free_space);
+    if target == 0 {
+        return Some(Vec::default());
+    }
+    if fs.size() < target {
+        return None;
+    }
+
+    // The fewest directories that can be enough is the number of layers when minimising the count.
+    let max_count = match objective {
+        Objective::Bytes => 0,
+        Objective::Count => most_freeable_sizes(fs, FileSystem::ROOT)
+            .iter()
+            .position(|size| *size >= target)?,
+    };
+    let mut knapsack = Knapsack::new(fs, target, max_count);
+    let mut deleted = knapsack.solve()?;
+    deleted.sort_by_key(|id| fs.path(*id));
+    Some(deleted)
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let fpath = args.next().expect("Path to input file is not given!");
+    if args.next_if_eq("capture").is_some() {
+        let stdout = std::io::stdout();
+        let mut w = std::io::BufWriter::new(stdout.lock());
+        capture(Path::new(&fpath), &mut w).unwrap();
+        return;
+    }
+    let is_strict = args.next_if_eq("strict").is_some();
+
+    let mut file_system = FileSystem::default();
+    let reader = BufReader::new(File::open(&fpath).unwrap());
+    replay(reader, &mut file_system, is_strict).unwrap();
+
+    if args.next_if_eq("shell").is_some() {
+        shell(&mut file_system).unwrap();
+        return;
+    }
+
+    if args.next_if_eq("plan").is_some() {
+        let mut objective = Objective::Bytes;
+        let mut disk_size = DISK_SIZE;
+        let mut required_space = REQUIRED_SPACE;
+        for arg in args {
+            match arg.split_once('=') {
+                None if arg == "bytes" => objective = Objective::Bytes,
+                None if arg == "count" => objective = Objective::Count,
+                Some(("disk", size)) => disk_size = parse_size(size).unwrap(),
+                Some(("need", size)) => required_space = parse_size(size).unwrap(),
+                _ => panic!("Unknown plan option {:?}!", arg),
+            }
+        }
+        match plan_deletion(&file_system, disk_size, required_space, objective) {
+            Some(directories) => {
+                let mut freed = 0;
+                for &directory in &directories {
+                    let size = file_system.directory_size(directory);
+                    println!("{}\t{}", size, file_system.path(directory));
+                    freed += size;
+                }
+                println!(
+                    "{} directories, {} bytes freed, {} bytes free",
+                    directories.len(),
+                    freed,
+                    disk_size.saturating_sub(file_system.size()) + freed
+                );
+            }
+            None => println!("not enough space can be freed"),
+        }
+        return;
+    }
+
+    if args.next_if_eq("materialize").is_some() {
+        let root = args
+            .next()
+            .expect("Path to the target directory is not given!");
+        materialize(&file_system, Path::new(&root)).unwrap();
+        return;
+    }
+
+    if args.next_if_eq("verify").is_some() {
+        verify(&file_system).unwrap();
+        return;
+    }
+
+    if args.next_if_eq("export").is_some() {
+        let stdout = std::io::stdout();
+        let mut w = std::io::BufWriter::new(stdout.lock());
+        match args.next().as_deref() {
+            Some("json") | None => export_json(&file_system, FileSystem::ROOT, 0, &mut w),
+            Some("du") => export_du(&file_system, &mut w),
+            Some("ncdu") => export_ncdu(&file_system, &mut w),
+            Some(format) => panic!("Unknown export format {:?}!", format),
+        }
+        .unwrap();
+        return;
+    }
+
+    if let Some(order) = args.next() {
+        let directories: Vec<usize> = match order.as_str() {
+            "pre-order" => file_system.pre_order(FileSystem::ROOT).collect(),
+            "post-order" => file_system.post_order(FileSystem::ROOT).collect(),
+            "breadth-first" => file_system.breadth_first(FileSystem::ROOT).collect(),
+            _ => panic!("Traversal order must be pre-order, post-order or breadth-first!"),
+        };
+        for directory in directories {
+            let size = file_system.directory_size(directory);
+            println!("dir {} {}", size, file_system.path(directory));
+            for &file in file_system.directories[directory].child_files.values() {
+                println!(
+                    "file {} {}",
+                    file_system.files[file].size,
+                    file_system.file_path(file)
+                );
+            }
+        }
+        return;
     }
 
     let mut total = 0;
-    for directory in DirectoryIter::from(&file_system) {
-        let mut directory = directory.borrow_mut();
-        let size = directory.size(true);
+    for directory in file_system.pre_order(FileSystem::ROOT) {
+        let size = file_system.directory_size(directory);
         if size <= 100000 {
             total += size;
         }
     }
     println!("{:?}", total);
 
-    let total = file_system.size(true);
+    let total = file_system.size();
     let mut min_deleted_size = u64::MAX;
-    for directory in DirectoryIter::from(&file_system) {
-        let mut directory = directory.borrow_mut();
-        let size = directory.size(true);
-        if total - size <= 40000000 {
+    for directory in file_system.pre_order(FileSystem::ROOT) {
+        let size = file_system.directory_size(directory);
+        if total - size <= DISK_SIZE - REQUIRED_SPACE {
             min_deleted_size = min_deleted_size.min(size);
         };
     }
     println!("{:?}", min_deleted_size);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../input/day07ex.txt");
+
+    #[test]
+    fn verify_matches_du_on_example() {
+        let mut fs = FileSystem::default();
+        let reader = BufReader::new(File::open(EXAMPLE).unwrap());
+        replay(reader, &mut fs, true).unwrap();
+        verify(&fs).unwrap();
+    }
+
+    #[test]
+    fn capture_round_trips() {
+        let root = env::temp_dir().join(format!("day07-capture-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("a/e")).unwrap();
+        std::fs::create_dir_all(root.join("d")).unwrap();
+        for (path, size) in [
+            ("b.txt", 14848514),
+            ("a/f", 29116),
+            ("a/e/i", 584),
+            ("d/k", 7214296),
+        ] {
+            File::create(root.join(path))
+                .unwrap()
+                .set_len(size)
+                .unwrap();
+        }
+        let mut transcript = Vec::default();
+        let result = capture(&root, &mut transcript);
+        std::fs::remove_dir_all(&root).unwrap();
+        result.unwrap();
+
+        let expected = [
+            "$ cd /",
+            "$ ls",
+            "dir a",
+            "dir d",
+            "14848514 b.txt",
+            "$ cd a",
+            "$ ls",
+            "dir e",
+            "29116 f",
+            "$ cd e",
+            "$ ls",
+            "584 i",
+            "$ cd ..",
+            "$ cd ..",
+            "$ cd d",
+            "$ ls",
+            "7214296 k",
+            "$ cd ..",
+        ];
+        assert_eq!(
+            expected.join("\n") + "\n",
+            String::from_utf8_lossy(&transcript)
+        );
+
+        let mut fs = FileSystem::default();
+        replay(transcript.as_slice(), &mut fs, true).unwrap();
+        assert_eq!(22092510, fs.size());
+        let sizes: Vec<(String, u64)> = fs
+            .pre_order(FileSystem::ROOT)
+            .map(|id| (fs.path(id), fs.directory_size(id)))
+            .collect();
+        assert_eq!(
+            vec![
+                (String::from("/"), 22092510),
+                (String::from("/a"), 29700),
+                (String::from("/a/e"), 584),
+                (String::from("/d"), 7214296),
+            ],
+            sizes
+        );
+    }
+
+    #[test]
+    fn replay_rejects_names_leading_out_of_directory() {
+        for name in ["..", ".", "../../escaped", "a/b"] {
+            let transcript = format!("$ cd /\n$ ls\ndir a\n$ cd a\n$ ls\n7 {}\n", name);
+            for is_strict in [false, true] {
+                let result = replay(transcript.as_bytes(), &mut FileSystem::default(), is_strict);
+                assert_eq!(
+                    std::io::ErrorKind::InvalidFilename,
+                    result.unwrap_err().kind()
+                );
+            }
+        }
+    }
+}
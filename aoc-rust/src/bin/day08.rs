@@ -1,130 +1,559 @@
 use std::{
     env,
     fs::File,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, Write},
 };
 
-fn main() {
-    let fpath = env::args()
-        .nth(1)
-        .expect("Path to input file is not given!");
-    let reader = BufReader::new(File::open(&fpath).unwrap());
-
-    // Read map
+/// Steps along the rows and columns of the grid.
+const AXES: [(isize, isize); 4] = [(-1, 0), (0, -1), (1, 0), (0, 1)];
 
-    let mut grid_tree_height = Vec::default();
-    let mut grid_visibility = Vec::default();
-    let mut grid_scenic_score = Vec::default();
+/// Steps along the rows, the columns and the diagonals of the grid.
+const ALL_DIRECTIONS: [(isize, isize); 8] = [
+    (-1, 0),
+    (0, -1),
+    (1, 0),
+    (0, 1),
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+];
 
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let mut row_tree_height = Vec::default();
-        let mut row_visibility = Vec::default();
-        let mut row_scenic_score = Vec::default();
-        for c in line.chars() {
-            row_tree_height.push(c.to_digit(10).unwrap());
-            row_visibility.push(false);
-            row_scenic_score.push(1u64);
+/// Reads a height map, either as rows of single digits or as rows of whitespace-separated
+/// numbers. Every row must have the same number of trees.
+fn parse_grid<R: BufRead>(reader: R) -> std::io::Result<Vec<Vec<u32>>> {
+    let mut grid_tree_height: Vec<Vec<u32>> = Vec::default();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid_height = |s: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}: invalid height {:?}", idx + 1, s),
+            )
+        };
+        let row_tree_height = if line.trim().contains(char::is_whitespace) {
+            line.split_whitespace()
+                .map(|s| s.parse().map_err(|_| invalid_height(s)))
+                .collect::<std::io::Result<Vec<u32>>>()?
+        } else {
+            line.trim()
+                .chars()
+                .map(|c| c.to_digit(10).ok_or_else(|| invalid_height(&c.to_string())))
+                .collect::<std::io::Result<Vec<u32>>>()?
+        };
+        if let Some(first_row) = grid_tree_height.first() {
+            if first_row.len() != row_tree_height.len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "line {}: expecting {} trees, found {}",
+                        idx + 1,
+                        first_row.len(),
+                        row_tree_height.len()
+                    ),
+                ));
+            }
         }
         grid_tree_height.push(row_tree_height);
-        grid_visibility.push(row_visibility);
-        grid_scenic_score.push(row_scenic_score);
     }
+    Ok(grid_tree_height)
+}
+
+/// Visibility from outside the grid and scenic score of every tree.
+struct Analysis {
+    grid_visibility: Vec<Vec<bool>>,
+    grid_scenic_score: Vec<Vec<u64>>,
+}
 
+/// Looks from every tree along each of the given directions. Each line of trees along a direction
+/// is walked starting from the edge it is looking at, keeping a stack of the trees seen so far
+/// whose heights never increase. A tree pops every shorter tree, which can never block the view
+/// of a later tree any more than the current one, so the whole pass is linear in the number of
+/// cells for each direction.
+fn analyze(grid_tree_height: &[Vec<u32>], directions: &[(isize, isize)]) -> Analysis {
     let height = grid_tree_height.len();
-    let width = grid_tree_height[0].len();
+    let width = grid_tree_height.first().map(|row| row.len()).unwrap_or(0);
+    let mut grid_visibility = vec![vec![false; width]; height];
+    let mut grid_scenic_score = vec![vec![1u64; width]; height];
 
-    // Part 01
+    let in_grid =
+        |i: isize, j: isize| (0..height as isize).contains(&i) && (0..width as isize).contains(&j);
+    let mut stack: Vec<(u32, u64)> = Vec::default();
+    for &(di, dj) in directions {
+        for i in 0..height as isize {
+            for j in 0..width as isize {
+                // Only start from trees at the edge the direction is looking at.
+                if in_grid(i + di, j + dj) {
+                    continue;
+                }
+                stack.clear();
+                let (mut ci, mut cj) = (i, j);
+                let mut pos = 0;
+                while in_grid(ci, cj) {
+                    let tree_height = grid_tree_height[ci as usize][cj as usize];
+                    while stack.last().is_some_and(|(h, _)| *h < tree_height) {
+                        stack.pop();
+                    }
+                    let distance = match stack.last() {
+                        Some((_, blocking_pos)) => pos - blocking_pos,
+                        None => {
+                            grid_visibility[ci as usize][cj as usize] = true;
+                            pos
+                        }
+                    };
+                    grid_scenic_score[ci as usize][cj as usize] *= distance;
+                    stack.push((tree_height, pos));
+                    ci -= di;
+                    cj -= dj;
+                    pos += 1;
+                }
+            }
+        }
+    }
 
-    for i in 0..height {
-        grid_visibility[i][0] = true;
-        grid_visibility[i][width - 1] = true;
+    Analysis {
+        grid_visibility,
+        grid_scenic_score,
     }
-    for j in 0..width {
-        grid_visibility[0][j] = true;
-        grid_visibility[height - 1][j] = true;
+}
+
+/// An 8-bit grayscale image of a grid, with every cell drawn as a square of `scale` pixels.
+struct Heatmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Heatmap {
+    fn new(grid_intensity: &[Vec<u8>], scale: usize) -> Self {
+        let height = grid_intensity.len() * scale;
+        let width = grid_intensity.first().map(|row| row.len()).unwrap_or(0) * scale;
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in grid_intensity {
+            for _ in 0..scale {
+                for intensity in row {
+                    pixels.extend(std::iter::repeat_n(*intensity, scale));
+                }
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
     }
 
-    let mut highest_from_top = grid_tree_height[0].clone();
-    let mut highest_from_left = Vec::with_capacity(height);
-    let mut highest_from_bottom = grid_tree_height[height - 1].clone();
-    let mut highest_from_right = Vec::with_capacity(height);
-    for i in 0..height {
-        highest_from_left.push(grid_tree_height[i][0]);
-        highest_from_right.push(grid_tree_height[i][width - 1]);
+    fn write_pgm<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        write!(w, "P5\n{} {}\n255\n", self.width, self.height)?;
+        w.write_all(&self.pixels)
     }
 
-    for i in 1..height - 1 {
-        for j in 1..width - 1 {
-            let visible_top = grid_tree_height[i][j] > highest_from_top[j];
-            let visible_left = grid_tree_height[i][j] > highest_from_left[i];
-            grid_visibility[i][j] |= visible_top || visible_left;
+    /// Writes a grayscale PNG. The image data is wrapped in uncompressed deflate blocks, which
+    /// every decoder accepts and which need no compressor.
+    fn write_png<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let mut raw = Vec::with_capacity((self.width + 1) * self.height);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
 
-            highest_from_top[j] = highest_from_top[j].max(grid_tree_height[i][j]);
-            highest_from_left[i] = highest_from_left[i].max(grid_tree_height[i][j]);
+        let mut zlib = vec![0x78, 0x01];
+        let mut blocks = raw.chunks(u16::MAX as usize).peekable();
+        if blocks.peek().is_none() {
+            zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+        }
+        while let Some(block) = blocks.next() {
+            zlib.push(blocks.peek().is_none() as u8);
+            zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+            zlib.extend_from_slice(block);
         }
+        zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8-bit grayscale, default compression and filtering, no interlacing.
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+        w.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_png_chunk(w, b"IHDR", &header)?;
+        write_png_chunk(w, b"IDAT", &zlib)?;
+        write_png_chunk(w, b"IEND", &[])
     }
-    for i in (1..height - 1).rev() {
-        for j in (1..width - 1).rev() {
-            let visible_bottom = grid_tree_height[i][j] > highest_from_bottom[j];
-            let visible_right = grid_tree_height[i][j] > highest_from_right[i];
-            grid_visibility[i][j] |= visible_bottom || visible_right;
+}
+
+fn write_png_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data));
+    w.write_all(&crc.to_be_bytes())
+}
 
-            highest_from_bottom[j] = highest_from_bottom[j].max(grid_tree_height[i][j]);
-            highest_from_right[i] = highest_from_right[i].max(grid_tree_height[i][j]);
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
         }
     }
+    !crc
+}
 
-    let count = grid_visibility.iter().flatten().filter(|x| **x).count();
-    println!("{}", count);
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for x in bytes {
+        a = (a + *x as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
 
-    // Part 02
+/// Maps scenic scores to intensities, relative to the highest score. The log scale keeps the few
+/// outstanding trees from washing out the rest of the forest.
+fn scenic_intensities(grid_scenic_score: &[Vec<u64>], is_log_scale: bool) -> Vec<Vec<u8>> {
+    let scale = |score: u64| {
+        if is_log_scale {
+            (score as f64).ln_1p()
+        } else {
+            score as f64
+        }
+    };
+    let max_score = grid_scenic_score
+        .iter()
+        .flatten()
+        .max()
+        .copied()
+        .unwrap_or(0);
+    let max_scaled = scale(max_score).max(f64::MIN_POSITIVE);
+    grid_scenic_score
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|score| (scale(*score) / max_scaled * 255.0).round() as u8)
+                .collect()
+        })
+        .collect()
+}
 
-    for i in 0..height {
-        grid_scenic_score[i][0] = 0;
-        grid_scenic_score[i][width - 1] = 0;
+fn write_csv<W: Write, T: ToString>(grid: &[Vec<T>], w: &mut W) -> std::io::Result<()> {
+    for row in grid {
+        let values: Vec<String> = row.iter().map(|x| x.to_string()).collect();
+        writeln!(w, "{}", values.join(","))?;
     }
-    for j in 0..width {
-        grid_scenic_score[0][j] = 0;
-        grid_scenic_score[height - 1][j] = 0;
+    Ok(())
+}
+
+/// Returns the row, the column and the score of the most scenic tree, the first one in reading
+/// order on ties.
+fn best_tree(grid_scenic_score: &[Vec<u64>]) -> Option<(usize, usize, u64)> {
+    let mut best: Option<(usize, usize, u64)> = None;
+    for (i, row) in grid_scenic_score.iter().enumerate() {
+        for (j, score) in row.iter().enumerate() {
+            if best.is_none_or(|(_, _, best_score)| *score > best_score) {
+                best = Some((i, j, *score));
+            }
+        }
     }
+    best
+}
 
-    let mut last_known_idx_top = Vec::with_capacity(width);
-    let mut last_known_idx_left = Vec::with_capacity(height);
-    let mut last_known_idx_bottom = Vec::with_capacity(width);
-    let mut last_known_idx_right = Vec::with_capacity(height);
-    for _ in 0..height {
-        last_known_idx_left.push([0usize; 10]);
-        last_known_idx_right.push([width - 1; 10]);
+/// Prints the height map with hidden trees dimmed to `.` and the most scenic tree bracketed.
+fn print_overlay(grid_tree_height: &[Vec<u32>], analysis: &Analysis) {
+    let cell_width = grid_tree_height
+        .iter()
+        .flatten()
+        .map(|h| h.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let best = best_tree(&analysis.grid_scenic_score);
+    for (i, row) in grid_tree_height.iter().enumerate() {
+        let mut line = String::default();
+        for (j, tree_height) in row.iter().enumerate() {
+            let label = if analysis.grid_visibility[i][j] {
+                tree_height.to_string()
+            } else {
+                String::from(".")
+            };
+            match best {
+                Some((bi, bj, _)) if (bi, bj) == (i, j) => {
+                    line.push_str(&format!("[{:>w$}]", tree_height, w = cell_width))
+                }
+                _ => line.push_str(&format!(" {:>w$} ", label, w = cell_width)),
+            }
+        }
+        println!("{}", line.trim_end());
     }
-    for _ in 0..width {
-        last_known_idx_top.push([0usize; 10]);
-        last_known_idx_bottom.push([height - 1; 10]);
+    if let Some((i, j, score)) = best {
+        println!(
+            "best tree: row {}, column {}, height {}, scenic score {}",
+            i + 1,
+            j + 1,
+            grid_tree_height[i][j],
+            score
+        );
     }
+}
+
+/// Cells crossed by the straight line between two trees, in order and including both ends,
+/// following Bresenham's algorithm.
+fn line_of_sight(from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+    let (mut i, mut j) = (from.0 as isize, from.1 as isize);
+    let (ti, tj) = (to.0 as isize, to.1 as isize);
+    let (di, dj) = ((ti - i).abs(), -(tj - j).abs());
+    let (si, sj) = ((ti - i).signum(), (tj - j).signum());
+    let mut err = di + dj;
+    let mut cells = vec![(i as usize, j as usize)];
+    while (i, j) != (ti, tj) {
+        let e2 = 2 * err;
+        if e2 >= dj {
+            err += dj;
+            i += si;
+        }
+        if e2 <= di {
+            err += di;
+            j += sj;
+        }
+        cells.push((i as usize, j as usize));
+    }
+    cells
+}
 
-    for i in 1..height - 1 {
-        for j in 1..width - 1 {
-            let height = grid_tree_height[i][j] as usize;
-            grid_scenic_score[i][j] *= (i - last_known_idx_top[j][height]) as u64;
-            grid_scenic_score[i][j] *= (j - last_known_idx_left[i][height]) as u64;
-            for t in 1..=height {
-                last_known_idx_top[j][t] = i;
-                last_known_idx_left[i][t] = j;
+/// Returns the first tree standing between `from` and `to` that is at least as tall as the tree
+/// at `from`, which blocks the view the same way it does along the axes.
+fn blocking_tree(
+    grid_tree_height: &[Vec<u32>],
+    from: (usize, usize),
+    to: (usize, usize),
+) -> Option<(usize, usize)> {
+    let viewer_height = grid_tree_height[from.0][from.1];
+    let cells = line_of_sight(from, to);
+    cells[1..cells.len().saturating_sub(1)]
+        .iter()
+        .find(|(i, j)| grid_tree_height[*i][*j] >= viewer_height)
+        .copied()
+}
+
+/// All trees that can be seen from the given tree, in reading order.
+fn viewshed(grid_tree_height: &[Vec<u32>], from: (usize, usize)) -> Vec<(usize, usize)> {
+    let mut visible = Vec::default();
+    for (i, row) in grid_tree_height.iter().enumerate() {
+        for j in 0..row.len() {
+            if (i, j) != from && blocking_tree(grid_tree_height, from, (i, j)).is_none() {
+                visible.push((i, j));
             }
         }
     }
-    for i in (1..height - 1).rev() {
-        for j in (1..width - 1).rev() {
-            let height = grid_tree_height[i][j] as usize;
-            grid_scenic_score[i][j] *= (last_known_idx_bottom[j][height] - i) as u64;
-            grid_scenic_score[i][j] *= (last_known_idx_right[i][height] - j) as u64;
-            for t in 0..=height {
-                last_known_idx_bottom[j][t] = i;
-                last_known_idx_right[i][t] = j;
+    visible
+}
+
+/// The `k` most scenic trees with their scores, the first ones in reading order on ties.
+fn top_scenic(grid_scenic_score: &[Vec<u64>], k: usize) -> Vec<((usize, usize), u64)> {
+    let mut trees: Vec<((usize, usize), u64)> = grid_scenic_score
+        .iter()
+        .enumerate()
+        .flat_map(|(i, row)| row.iter().enumerate().map(move |(j, s)| ((i, j), *s)))
+        .collect();
+    trees.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+    trees.truncate(k);
+    trees
+}
+
+/// Parses a tree location written as `row,column`, both counted from 1.
+fn parse_location(grid_tree_height: &[Vec<u32>], s: &str) -> std::io::Result<(usize, usize)> {
+    let invalid = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid tree location {:?}", s),
+        )
+    };
+    let (i, j) = s.split_once(',').ok_or_else(invalid)?;
+    let i: usize = i.trim().parse().map_err(|_| invalid())?;
+    let j: usize = j.trim().parse().map_err(|_| invalid())?;
+    match grid_tree_height.get(i.wrapping_sub(1)) {
+        Some(row) if (1..=row.len()).contains(&j) => Ok((i - 1, j - 1)),
+        _ => Err(invalid()),
+    }
+}
+
+fn format_location((i, j): (usize, usize)) -> String {
+    format!("{},{}", i + 1, j + 1)
+}
+
+fn run_query(
+    grid_tree_height: &[Vec<u32>],
+    analysis: &Analysis,
+    line: &str,
+) -> std::io::Result<()> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    match tokens.as_slice() {
+        ["see", from, to] => {
+            let from = parse_location(grid_tree_height, from)?;
+            let to = parse_location(grid_tree_height, to)?;
+            match blocking_tree(grid_tree_height, from, to) {
+                None => println!("{}: yes", line.trim()),
+                Some(blocking) => println!(
+                    "{}: no, blocked by {} (height {})",
+                    line.trim(),
+                    format_location(blocking),
+                    grid_tree_height[blocking.0][blocking.1]
+                ),
+            }
+        }
+        ["viewshed", from] => {
+            let from = parse_location(grid_tree_height, from)?;
+            let visible = viewshed(grid_tree_height, from);
+            println!("{}: {} trees", line.trim(), visible.len());
+            for (i, j) in visible {
+                println!(
+                    "  {} height {}",
+                    format_location((i, j)),
+                    grid_tree_height[i][j]
+                );
+            }
+        }
+        ["top", k] => {
+            let k = k.parse().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid count {:?}", k),
+                )
+            })?;
+            println!("{}:", line.trim());
+            for ((i, j), score) in top_scenic(&analysis.grid_scenic_score, k) {
+                println!(
+                    "  {} height {} score {}",
+                    format_location((i, j)),
+                    grid_tree_height[i][j],
+                    score
+                );
             }
         }
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "expecting \"see R,C R,C\", \"viewshed R,C\" or \"top K\"",
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Answers queries read from stdin, one per line.
+fn query(grid_tree_height: &[Vec<u32>], analysis: &Analysis) {
+    for line in std::io::stdin().lock().lines() {
+        let line = line.unwrap();
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Err(e) = run_query(grid_tree_height, analysis, &line) {
+            println!("{}: {}", line.trim(), e);
+        }
     }
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let fpath = args.next().expect("Path to input file is not given!");
+    let directions: &[(isize, isize)] = if args.next_if_eq("diagonal").is_some() {
+        &ALL_DIRECTIONS
+    } else {
+        &AXES
+    };
+    let reader = BufReader::new(File::open(&fpath).unwrap());
+
+    // Read map
+
+    let grid_tree_height = parse_grid(reader).unwrap();
+    let analysis = analyze(&grid_tree_height, directions);
+
+    if args.next_if_eq("query").is_some() {
+        query(&grid_tree_height, &analysis);
+        return;
+    }
+
+    if args.next_if_eq("overlay").is_some() {
+        print_overlay(&grid_tree_height, &analysis);
+        return;
+    }
+
+    if args.next_if_eq("heatmap").is_some() {
+        let kind = args
+            .next()
+            .expect("Heatmap must be of visibility or scenic!");
+        let format = args
+            .next()
+            .expect("Heatmap format must be pgm, png or csv!");
+        let mut is_log_scale = false;
+        let mut scale = 1;
+        for arg in args {
+            match arg.split_once('=') {
+                None if arg == "log" => is_log_scale = true,
+                Some(("scale", n)) => scale = n.parse().expect("Scale must be a number!"),
+                _ => panic!("Unknown heatmap option {:?}!", arg),
+            }
+        }
+
+        let stdout = std::io::stdout();
+        let mut w = std::io::BufWriter::new(stdout.lock());
+        let grid_intensity: Vec<Vec<u8>> = match kind.as_str() {
+            "visibility" if format == "csv" => {
+                let grid_visible: Vec<Vec<u8>> = analysis
+                    .grid_visibility
+                    .iter()
+                    .map(|row| row.iter().map(|visible| *visible as u8).collect())
+                    .collect();
+                write_csv(&grid_visible, &mut w).unwrap();
+                return;
+            }
+            "scenic" if format == "csv" => {
+                write_csv(&analysis.grid_scenic_score, &mut w).unwrap();
+                return;
+            }
+            "visibility" => analysis
+                .grid_visibility
+                .iter()
+                .map(|row| row.iter().map(|visible| *visible as u8 * 255).collect())
+                .collect(),
+            "scenic" => scenic_intensities(&analysis.grid_scenic_score, is_log_scale),
+            _ => panic!("Heatmap must be of visibility or scenic!"),
+        };
+        let heatmap = Heatmap::new(&grid_intensity, scale);
+        match format.as_str() {
+            "pgm" => heatmap.write_pgm(&mut w).unwrap(),
+            "png" => heatmap.write_png(&mut w).unwrap(),
+            _ => panic!("Heatmap format must be pgm, png or csv!"),
+        }
+        return;
+    }
+
+    // Part 01
+
+    let count = analysis
+        .grid_visibility
+        .iter()
+        .flatten()
+        .filter(|x| **x)
+        .count();
+    println!("{}", count);
+
+    // Part 02
 
-    let max_score = grid_scenic_score.iter().flatten().max().unwrap();
+    let max_score = analysis
+        .grid_scenic_score
+        .iter()
+        .flatten()
+        .max()
+        .copied()
+        .unwrap_or(0);
     println!("{}", max_score);
 }
@@ -1,8 +1,8 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     env,
     fs::File,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, Write},
 };
 
 #[derive(Default, Debug, Hash, PartialEq, Eq, Clone)]
@@ -13,18 +13,15 @@ struct Position {
 
 impl Position {
     fn distance(&self, other: &Self) -> u64 {
-        let xdiff = (self.x - other.x).abs() as u64;
-        let ydiff = (self.y - other.y).abs() as u64;
+        let xdiff = (self.x - other.x).unsigned_abs();
+        let ydiff = (self.y - other.y).unsigned_abs();
         xdiff.max(ydiff)
     }
 
     fn step(&mut self, direction: &Direction) {
-        match direction {
-            Direction::Left => self.x -= 1,
-            Direction::Right => self.x += 1,
-            Direction::Up => self.y += 1,
-            Direction::Down => self.y -= 1,
-        }
+        let (dx, dy) = direction.offset();
+        self.x += dx;
+        self.y += dy;
     }
 }
 
@@ -69,6 +66,38 @@ enum Direction {
     Right,
     Up,
     Down,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+impl Direction {
+    fn offset(&self) -> (i64, i64) {
+        match self {
+            Self::Left => (-1, 0),
+            Self::Right => (1, 0),
+            Self::Up => (0, 1),
+            Self::Down => (0, -1),
+            Self::UpLeft => (-1, 1),
+            Self::UpRight => (1, 1),
+            Self::DownLeft => (-1, -1),
+            Self::DownRight => (1, -1),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Left => "L",
+            Self::Right => "R",
+            Self::Up => "U",
+            Self::Down => "D",
+            Self::UpLeft => "UL",
+            Self::UpRight => "UR",
+            Self::DownLeft => "DL",
+            Self::DownRight => "DR",
+        }
+    }
 }
 
 impl TryFrom<&str> for Direction {
@@ -80,6 +109,10 @@ impl TryFrom<&str> for Direction {
             "R" => Ok(Self::Right),
             "U" => Ok(Self::Up),
             "D" => Ok(Self::Down),
+            "UL" => Ok(Self::UpLeft),
+            "UR" => Ok(Self::UpRight),
+            "DL" => Ok(Self::DownLeft),
+            "DR" => Ok(Self::DownRight),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "unknown direction",
@@ -118,38 +151,506 @@ impl TryFrom<&str> for Instruction {
     }
 }
 
-fn simulate(rope: &mut Rope, instructions: &[Instruction]) -> usize {
-    let mut visited: HashSet<Position> = HashSet::default();
-    if let Some(tail) = rope.knots.last() {
-        visited.insert(tail.clone());
-    }
+/// Moves the rope through all instructions and returns the cells visited by every knot, from the
+/// head to the tail.
+fn simulate(rope: &mut Rope, instructions: &[Instruction]) -> Vec<HashSet<Position>> {
+    let mut visited: Vec<HashSet<Position>> = rope
+        .knots
+        .iter()
+        .map(|knot| HashSet::from([knot.clone()]))
+        .collect();
     for inst in instructions {
         for _ in 0..inst.steps {
             rope.step(&inst.direction);
-            if let Some(tail) = rope.knots.last() {
-                visited.insert(tail.clone());
+            for (knot, knot_visited) in rope.knots.iter().zip(visited.iter_mut()) {
+                knot_visited.insert(knot.clone());
+            }
+        }
+    }
+    visited
+}
+
+/// The four orientations a straight run of cells can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Orientation {
+    Horizontal,
+    Vertical,
+    Diagonal,
+    AntiDiagonal,
+}
+
+impl Orientation {
+    const ALL: [Self; 4] = [
+        Self::Horizontal,
+        Self::Vertical,
+        Self::Diagonal,
+        Self::AntiDiagonal,
+    ];
+
+    fn of(dx: i64, dy: i64) -> Self {
+        match (dx, dy) {
+            (_, 0) => Self::Horizontal,
+            (0, _) => Self::Vertical,
+            _ if dx == dy => Self::Diagonal,
+            _ => Self::AntiDiagonal,
+        }
+    }
+
+    /// Coefficients `(a, b)` such that every cell of a line satisfies `a * x + b * y = key`.
+    fn coefficients(&self) -> (i64, i64) {
+        match self {
+            Self::Horizontal => (0, 1),
+            Self::Vertical => (1, 0),
+            Self::Diagonal => (-1, 1),
+            Self::AntiDiagonal => (1, 1),
+        }
+    }
+
+    /// Returns the line a cell belongs to and the cell's coordinate along that line.
+    fn locate(&self, p: &Position) -> (i64, i64) {
+        let (a, b) = self.coefficients();
+        let along = match self {
+            Self::Vertical => p.y,
+            _ => p.x,
+        };
+        (a * p.x + b * p.y, along)
+    }
+
+    /// Returns the cell of the given line at the given coordinate along it.
+    fn position(&self, key: i64, along: i64) -> Position {
+        match self {
+            Self::Horizontal => Position { x: along, y: key },
+            Self::Vertical => Position { x: key, y: along },
+            Self::Diagonal => Position {
+                x: along,
+                y: key + along,
+            },
+            Self::AntiDiagonal => Position {
+                x: along,
+                y: key - along,
+            },
+        }
+    }
+}
+
+/// Disjoint ranges of integers, stored as a map from the start to the inclusive end of each
+/// range. Touching ranges are merged.
+#[derive(Debug, Default)]
+struct IntervalSet {
+    ranges: BTreeMap<i64, i64>,
+}
+
+impl IntervalSet {
+    fn insert(&mut self, mut start: i64, mut end: i64) {
+        if let Some((&s, &e)) = self.ranges.range(..=start).next_back() {
+            if e + 1 >= start {
+                start = s;
+                end = end.max(e);
+            }
+        }
+        let overlapping: Vec<(i64, i64)> = self
+            .ranges
+            .range(start..=end.saturating_add(1))
+            .map(|(s, e)| (*s, *e))
+            .collect();
+        for (s, e) in overlapping {
+            self.ranges.remove(&s);
+            end = end.max(e);
+        }
+        self.ranges.insert(start, end);
+    }
+
+    fn contains(&self, x: i64) -> bool {
+        self.ranges
+            .range(..=x)
+            .next_back()
+            .is_some_and(|(_, e)| *e >= x)
+    }
+
+    fn len(&self) -> u64 {
+        self.ranges.iter().map(|(s, e)| (e - s + 1) as u64).sum()
+    }
+}
+
+/// Cells visited by a knot, kept as runs along the rows, the columns and the diagonals, so that a
+/// long straight move is stored in constant space. Single cells are kept in the rows.
+#[derive(Debug, Default)]
+struct VisitedCells {
+    lines: BTreeMap<(Orientation, i64), IntervalSet>,
+}
+
+impl VisitedCells {
+    fn insert(&mut self, p: &Position) {
+        self.insert_run(p, (1, 0), 1);
+    }
+
+    /// Inserts `len` cells starting at `p` and advancing by `(dx, dy)`.
+    fn insert_run(&mut self, p: &Position, (dx, dy): (i64, i64), len: u64) {
+        if len == 0 {
+            return;
+        }
+        let orientation = Orientation::of(dx, dy);
+        let last = Position {
+            x: p.x + dx * (len as i64 - 1),
+            y: p.y + dy * (len as i64 - 1),
+        };
+        let (key, start) = orientation.locate(p);
+        let (_, end) = orientation.locate(&last);
+        self.lines
+            .entry((orientation, key))
+            .or_default()
+            .insert(start.min(end), start.max(end));
+    }
+
+    /// Number of orientations with a run covering the cell.
+    fn runs_containing(&self, p: &Position) -> usize {
+        Orientation::ALL
+            .iter()
+            .filter(|orientation| {
+                let (key, along) = orientation.locate(p);
+                self.lines
+                    .get(&(**orientation, key))
+                    .is_some_and(|ranges| ranges.contains(along))
+            })
+            .count()
+    }
+
+    /// Counts the visited cells. Runs of the same orientation never overlap, so the only cells
+    /// counted more than once are where lines of different orientations cross.
+    fn len(&self) -> u64 {
+        let mut len: u64 = self.lines.values().map(IntervalSet::len).sum();
+        let mut crossings = HashSet::new();
+        for (i, o1) in Orientation::ALL.iter().enumerate() {
+            for o2 in &Orientation::ALL[i + 1..] {
+                self.find_crossings(*o1, *o2, &mut crossings);
+            }
+        }
+        for p in crossings {
+            let count = self.runs_containing(&p);
+            len -= count as u64 - 1;
+        }
+        len
+    }
+
+    /// Finds the cells where runs of `o1` cross runs of `o2`. Given by the keys of both lines
+    /// through them, the runs of one orientation become segments crossing those of the other at
+    /// right angles. A sweep over the keys of `o1` keeps the runs of `o2` that span the current
+    /// key, so every run of `o1` only meets the runs it crosses.
+    fn find_crossings(&self, o1: Orientation, o2: Orientation, crossings: &mut HashSet<Position>) {
+        // Events are ordered by the key of `o1`, then runs of `o2` start, runs of `o1` look them
+        // up, and runs of `o2` end.
+        let mut events = Vec::default();
+        for ((orientation, key), ranges) in &self.lines {
+            for (&start, &end) in &ranges.ranges {
+                let first = orientation.position(*key, start);
+                let last = orientation.position(*key, end);
+                if *orientation == o1 {
+                    let (from, to) = (o2.locate(&first).0, o2.locate(&last).0);
+                    events.push((*key, 1, from.min(to), from.max(to)));
+                } else if *orientation == o2 {
+                    let (from, to) = (o1.locate(&first).0, o1.locate(&last).0);
+                    events.push((from.min(to), 0, *key, *key));
+                    events.push((from.max(to), 2, *key, *key));
+                }
+            }
+        }
+        events.sort_unstable();
+
+        let (a1, b1) = o1.coefficients();
+        let (a2, b2) = o2.coefficients();
+        let det = a1 * b2 - a2 * b1;
+        // Diagonals only cross on a cell when their keys have the same parity.
+        let class = |key: i64| if det.abs() == 2 { key.rem_euclid(2) } else { 0 };
+        let mut active: BTreeMap<(i64, i64), usize> = BTreeMap::default();
+        for (k1, kind, from, to) in events {
+            match kind {
+                0 => *active.entry((class(from), from)).or_default() += 1,
+                2 => {
+                    let count = active.get_mut(&(class(from), from)).expect("run started");
+                    *count -= 1;
+                    if *count == 0 {
+                        active.remove(&(class(from), from));
+                    }
+                }
+                _ => {
+                    let class = class(k1);
+                    for &(_, k2) in active.range((class, from)..=(class, to)).map(|(k, _)| k) {
+                        crossings.insert(Position {
+                            x: (k1 * b2 - k2 * b1) / det,
+                            y: (a1 * k2 - a2 * k1) / det,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Same as `simulate`, except that a move stops being simulated step by step as soon as a step
+/// moves every knot the same way as the head. The rope then keeps its shape for the rest of the
+/// move, so every knot covers a straight run of cells that is recorded at once.
+fn simulate_fast(rope: &mut Rope, instructions: &[Instruction]) -> Vec<VisitedCells> {
+    let mut visited: Vec<VisitedCells> = rope
+        .knots
+        .iter()
+        .map(|knot| {
+            let mut knot_visited = VisitedCells::default();
+            knot_visited.insert(knot);
+            knot_visited
+        })
+        .collect();
+    for inst in instructions {
+        let (dx, dy) = inst.direction.offset();
+        let mut remaining = inst.steps;
+        while remaining > 0 {
+            let before = rope.knots.clone();
+            rope.step(&inst.direction);
+            remaining -= 1;
+            for (knot, knot_visited) in rope.knots.iter().zip(visited.iter_mut()) {
+                knot_visited.insert(knot);
+            }
+
+            let is_straight = rope
+                .knots
+                .iter()
+                .zip(before.iter())
+                .all(|(knot, prev)| knot.x - prev.x == dx && knot.y - prev.y == dy);
+            if is_straight && remaining > 0 {
+                for (knot, knot_visited) in rope.knots.iter_mut().zip(visited.iter_mut()) {
+                    knot.step(&inst.direction);
+                    knot_visited.insert_run(knot, (dx, dy), remaining);
+                    knot.x += dx * (remaining as i64 - 1);
+                    knot.y += dy * (remaining as i64 - 1);
+                }
+                remaining = 0;
             }
         }
     }
-    visited.len()
+    visited
+}
+
+/// Smallest and largest coordinates reached by any of the positions.
+fn bounds<'a>(positions: impl Iterator<Item = &'a Position>) -> (Position, Position) {
+    let mut min = Position::default();
+    let mut max = Position::default();
+    for p in positions {
+        min.x = min.x.min(p.x);
+        min.y = min.y.min(p.y);
+        max.x = max.x.max(p.x);
+        max.y = max.y.max(p.y);
+    }
+    (min, max)
+}
+
+/// Label of a knot in the puzzle's diagrams. The tail of a two-knot rope is `T`, otherwise knots
+/// after the head are numbered, wrapping around past 9.
+fn knot_label(i: usize, knot_count: usize) -> char {
+    match i {
+        0 => 'H',
+        1 if knot_count == 2 => 'T',
+        i => char::from_digit((i % 10) as u32, 10).unwrap(),
+    }
 }
 
-fn part01(instructions: &[Instruction]) {
-    let mut rope = Rope::new(2);
-    let count = simulate(&mut rope, instructions);
-    println!("{}", count);
+/// Draws the rope within the given bounds the way the puzzle does, with the start marked `s` and
+/// a note after every row where a knot covers others.
+fn render_rope(knots: &[Position], min: &Position, max: &Position) -> String {
+    let mut lines = Vec::default();
+    for y in (min.y..=max.y).rev() {
+        let mut line = String::default();
+        let mut notes = Vec::default();
+        for x in min.x..=max.x {
+            let mut labels: Vec<char> = knots
+                .iter()
+                .enumerate()
+                .filter(|(_, knot)| knot.x == x && knot.y == y)
+                .map(|(i, _)| knot_label(i, knots.len()))
+                .collect();
+            if x == 0 && y == 0 {
+                labels.push('s');
+            }
+            match labels.split_first() {
+                None => line.push('.'),
+                Some((label, [])) => line.push(*label),
+                Some((label, covered)) => {
+                    line.push(*label);
+                    let covered: Vec<String> = covered.iter().map(char::to_string).collect();
+                    notes.push(format!("({} covers {})", label, covered.join(", ")));
+                }
+            }
+        }
+        if !notes.is_empty() {
+            line.push_str("  ");
+            line.push_str(&notes.join(" "));
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// Draws the cells visited by a knot, with the start marked `s`.
+fn render_visited(visited: &HashSet<Position>, min: &Position, max: &Position) -> String {
+    let mut lines = Vec::default();
+    for y in (min.y..=max.y).rev() {
+        let line: String = (min.x..=max.x)
+            .map(|x| {
+                let p = Position { x, y };
+                if p == Position::default() {
+                    's'
+                } else if visited.contains(&p) {
+                    '#'
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        lines.push(line);
+    }
+    lines.join("\n")
 }
 
-fn part02(instructions: &[Instruction]) {
-    let mut rope = Rope::new(10);
-    let count = simulate(&mut rope, instructions);
-    println!("{}", count);
+/// Prints the rope after every step as in the puzzle's walkthrough, followed by the cells the tail
+/// visited. The view is fitted to everywhere the rope goes.
+fn print_ascii(knot_count: usize, instructions: &[Instruction]) {
+    let mut rope = Rope::new(knot_count);
+    let mut states = vec![rope.knots.clone()];
+    for inst in instructions {
+        for _ in 0..inst.steps {
+            rope.step(&inst.direction);
+            states.push(rope.knots.clone());
+        }
+    }
+    let (min, max) = bounds(states.iter().flatten());
+
+    println!("== Initial State ==\n");
+    println!("{}\n", render_rope(&states[0], &min, &max));
+    let mut state_idx = 1;
+    for inst in instructions {
+        println!("== {} {} ==\n", inst.direction.name(), inst.steps);
+        for _ in 0..inst.steps {
+            println!("{}\n", render_rope(&states[state_idx], &min, &max));
+            state_idx += 1;
+        }
+    }
+
+    let tail_visited: HashSet<Position> = states
+        .iter()
+        .filter_map(|knots| knots.last().cloned())
+        .collect();
+    println!("{}", render_visited(&tail_visited, &min, &max));
+}
+
+/// Renders the path of every knot as an SVG, with the cells visited by the tail shaded and the
+/// start and end points of every knot marked. The view box is fitted to the paths.
+fn write_svg<W: Write>(
+    knot_count: usize,
+    instructions: &[Instruction],
+    w: &mut W,
+) -> std::io::Result<()> {
+    let mut rope = Rope::new(knot_count);
+    let mut trajectories: Vec<Vec<Position>> =
+        rope.knots.iter().map(|knot| vec![knot.clone()]).collect();
+    for inst in instructions {
+        for _ in 0..inst.steps {
+            rope.step(&inst.direction);
+            for (knot, trajectory) in rope.knots.iter().zip(trajectories.iter_mut()) {
+                // Keep only the corners of the path.
+                if trajectory.len() >= 2 {
+                    let a = &trajectory[trajectory.len() - 2];
+                    let b = &trajectory[trajectory.len() - 1];
+                    if (b.x - a.x, b.y - a.y) == (knot.x - b.x, knot.y - b.y) {
+                        trajectory.pop();
+                    }
+                }
+                if trajectory.last() != Some(knot) {
+                    trajectory.push(knot.clone());
+                }
+            }
+        }
+    }
+    let tail_visited: Vec<Position> = {
+        let mut rope = Rope::new(knot_count);
+        let mut visited = simulate(&mut rope, instructions);
+        let mut cells: Vec<Position> = visited.pop().unwrap_or_default().into_iter().collect();
+        cells.sort_by_key(|p| (-p.y, p.x));
+        cells
+    };
+
+    // SVG's y axis points down, so rows are flipped.
+    let (min, max) = bounds(trajectories.iter().flatten());
+    let (view_width, view_height) = (max.x - min.x + 2, max.y - min.y + 2);
+    let pixels_per_cell = (800 / view_width.max(view_height)).clamp(1, 40);
+    writeln!(
+        w,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        min.x as f64 - 1.0,
+        -max.y as f64 - 1.0,
+        view_width,
+        view_height,
+        view_width * pixels_per_cell,
+        view_height * pixels_per_cell
+    )?;
+    writeln!(
+        w,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="white"/>"#,
+        min.x as f64 - 1.0,
+        -max.y as f64 - 1.0,
+        view_width,
+        view_height
+    )?;
+    writeln!(w, r##"<g fill="#cccccc">"##)?;
+    for p in &tail_visited {
+        writeln!(
+            w,
+            r#"<rect x="{}" y="{}" width="1" height="1"/>"#,
+            p.x as f64 - 0.5,
+            -p.y as f64 - 0.5
+        )?;
+    }
+    writeln!(w, "</g>")?;
+
+    let colour = |i: usize| format!("hsl({}, 80%, 45%)", i * 360 / knot_count.max(1));
+    for (i, trajectory) in trajectories.iter().enumerate().rev() {
+        let points: Vec<String> = trajectory
+            .iter()
+            .map(|p| format!("{},{}", p.x, -p.y))
+            .collect();
+        writeln!(
+            w,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="0.15" stroke-linejoin="round"><title>knot {}</title></polyline>"#,
+            points.join(" "),
+            colour(i),
+            knot_label(i, knot_count)
+        )?;
+    }
+    for (i, trajectory) in trajectories.iter().enumerate().rev() {
+        if let Some(end) = trajectory.last() {
+            writeln!(
+                w,
+                r#"<circle cx="{}" cy="{}" r="0.3" fill="{}"><title>knot {} end</title></circle>"#,
+                end.x,
+                -end.y,
+                colour(i),
+                knot_label(i, knot_count)
+            )?;
+        }
+    }
+    writeln!(
+        w,
+        r#"<rect x="-0.35" y="-0.35" width="0.7" height="0.7" fill="none" stroke="black" stroke-width="0.1"><title>start</title></rect>"#
+    )?;
+    writeln!(w, "</svg>")
 }
 
 fn main() {
-    let fpath = env::args()
-        .nth(1)
-        .expect("Path to input file is not given!");
+    let mut args = env::args().skip(1).peekable();
+    let fpath = args.next().expect("Path to input file is not given!");
+    let render_mode = args.next_if(|arg| arg == "ascii" || arg == "svg");
+    let is_per_step = args.next_if_eq("per-step").is_some();
+    let is_reporting_knots = args.next_if_eq("knots").is_some();
+    let mut knot_counts: Vec<usize> = args
+        .map(|s| s.parse().expect("Knot count must be a number!"))
+        .collect();
 
     let reader = BufReader::new(File::open(&fpath).unwrap());
     let instructions: Vec<Instruction> = reader
@@ -159,6 +660,96 @@ fn main() {
         .map(Result::unwrap)
         .collect();
 
-    part01(&instructions);
-    part02(&instructions);
+    if let Some(render_mode) = render_mode {
+        // Both views draw the same single rope, the longer one by default.
+        let knot_count = match knot_counts.as_slice() {
+            [] => 10,
+            [knot_count] => *knot_count,
+            _ => panic!("Rendering takes a single knot count!"),
+        };
+        if render_mode == "ascii" {
+            print_ascii(knot_count, &instructions);
+        } else {
+            let stdout = std::io::stdout();
+            let mut w = std::io::BufWriter::new(stdout.lock());
+            write_svg(knot_count, &instructions, &mut w).unwrap();
+        }
+        return;
+    }
+
+    if knot_counts.is_empty() {
+        knot_counts = vec![2, 10];
+    }
+
+    for knot_count in knot_counts {
+        let mut rope = Rope::new(knot_count);
+        let visited_counts: Vec<u64> = if is_per_step {
+            simulate(&mut rope, &instructions)
+                .iter()
+                .map(|knot_visited| knot_visited.len() as u64)
+                .collect()
+        } else {
+            simulate_fast(&mut rope, &instructions)
+                .iter()
+                .map(VisitedCells::len)
+                .collect()
+        };
+        if is_reporting_knots {
+            println!("{} knots:", knot_count);
+            for (i, count) in visited_counts.iter().enumerate() {
+                println!("  knot {}: {} cells", i, count);
+            }
+        } else {
+            println!("{}", visited_counts.last().copied().unwrap_or(0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_instructions(name: &str) -> Vec<Instruction> {
+        let path = format!("{}/../input/{}", env!("CARGO_MANIFEST_DIR"), name);
+        BufReader::new(File::open(path).unwrap())
+            .lines()
+            .map(|line| Instruction::try_from(line.unwrap().as_str()).unwrap())
+            .collect()
+    }
+
+    fn assert_fast_matches_per_step(instructions: &[Instruction]) {
+        for knot_count in [1, 2, 3, 10] {
+            let per_step: Vec<u64> = simulate(&mut Rope::new(knot_count), instructions)
+                .iter()
+                .map(|knot_visited| knot_visited.len() as u64)
+                .collect();
+            let fast: Vec<u64> = simulate_fast(&mut Rope::new(knot_count), instructions)
+                .iter()
+                .map(VisitedCells::len)
+                .collect();
+            assert_eq!(per_step, fast, "{} knots", knot_count);
+        }
+    }
+
+    #[test]
+    fn fast_matches_per_step_on_example() {
+        assert_fast_matches_per_step(&read_instructions("day09ex.txt"));
+    }
+
+    #[test]
+    fn fast_matches_per_step_on_input() {
+        assert_fast_matches_per_step(&read_instructions("day09.txt"));
+    }
+
+    #[test]
+    fn fast_matches_per_step_with_diagonal_moves() {
+        let instructions: Vec<Instruction> = [
+            "UR 8", "L 5", "DL 12", "U 3", "DR 7", "R 20", "UL 9", "D 15", "UR 11", "DL 4", "L 6",
+            "DR 13", "U 9", "UL 3", "R 2",
+        ]
+        .iter()
+        .map(|line| Instruction::try_from(*line).unwrap())
+        .collect();
+        assert_fast_matches_per_step(&instructions);
+    }
 }